@@ -1,4 +1,5 @@
-use std::f64::MAX;
+use std::borrow::Cow;
+use std::f64::consts::PI;
 use std::fs;
 use std::time::Instant;
 
@@ -10,9 +11,12 @@ use std::fmt::Write;
 
 use crate::color::write_color;
 use crate::color::Color;
-use crate::material::scatter;
+use crate::hittable::HitRecord;
+use crate::material::{emitted, sample};
+use crate::media::{Crossing, MediumStack};
+use crate::normal_map::perturb_normal;
 use crate::random_double;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayType};
 use crate::vec::*;
 use crate::vec3;
 use crate::Scene;
@@ -88,7 +92,7 @@ impl Camera {
 
         let pixel_00_loc = viewport_upper_left + pixel_delta_u / 2. + pixel_delta_v / 2.;
 
-        let DBG_instant = Instant::now();
+        let dbg_instant = Instant::now();
 
         for y in 0..self.image_height {
             for x in 0..self.image_width {
@@ -112,51 +116,131 @@ impl Camera {
                     let ray = Ray {
                         origin: self.eye,
                         direction: -self.eye + sample_pixel,
+                        ray_type: RayType::Camera,
                     };
                     // first hit every object using the ray
                     // TODO the hit code definitely has to be changed to account for
                     // multiple rays hitting the same part of the object .
                     // isn't this algorithm n^2?
 
-                    total_color += Self::ray_color(&ray, scene, self.max_depth);
+                    total_color +=
+                        Self::ray_color(&ray, scene, &MediumStack::default(), self.max_depth);
                 }
                 // divide color by num samples, clamp at 1
                 write_color(&mut buf, total_color, self.samples_per_pixel)?;
             }
         }
 
-        dbg!(DBG_instant.elapsed());
+        dbg!(dbg_instant.elapsed());
 
         // write the buffer to a ppm file
         fs::write("image.ppm", buf)?;
 
         Ok(())
     }
-    pub fn ray_color(ray: &Ray, scene: &mut Scene, depth_remaining: u32) -> Color {
+    /// `media` is every dielectric that the ray is inside of, see
+    /// `MediumStack`
+    pub fn ray_color(
+        ray: &Ray,
+        scene: &mut Scene,
+        media: &MediumStack,
+        depth_remaining: u32,
+    ) -> Color {
         if depth_remaining == 0 {
             return vec3![0., 0., 0.];
         }
         // BIG, BIG SUBTLE BUG, IF YOU USE 0, THE NEW DIFFUSE RAYS JUST SCATTER OFF THE SURFACE, BUT THEY MIGHT SPAWN BEHIND THE SPHERE, CAUSING LIGHT TO NOT BOUNCE ANYWHERE LOL
-        if let Some(hit_record) = scene.hit(ray, 0.001..MAX) {
-            // now move everything to a range of 0 to 1 and return the color
-            if let Some(scatter) = scatter(
-                &hit_record.material.as_ref().unwrap().borrow(),
-                &ray.direction,
-                &hit_record,
-            ) {
-                // each bounce reduces light, attenuation / power droppoff / bounces away
-                scatter.attenuation
-                    * Self::ray_color(&scatter.scattered, scene, depth_remaining - 1)
-            } else {
-                vec3![0., 0., 0.]
+        let hit = scene.hit(ray, 0.001..f64::MAX);
+        let t_end = hit
+            .as_ref()
+            .map_or(f64::INFINITY, |hit_record| hit_record.t);
+
+        // colored glass and liquids soak up light all the way through, not
+        // just at the surface
+        let absorbed = media.transmittance(t_end * ray.direction.len());
+        absorbed * Self::hit_color(ray, hit, t_end, scene, media, depth_remaining)
+    }
+
+    /// The light coming back along `ray` from `hit` (or the sky), before
+    /// anything that we're inside of soaks some of it up
+    fn hit_color(
+        ray: &Ray,
+        hit: Option<HitRecord>,
+        t_end: f64,
+        scene: &mut Scene,
+        media: &MediumStack,
+        depth_remaining: u32,
+    ) -> Color {
+        // the ray might bump into the fog before it gets to whatever it hit
+        // (or the sky). The fog is only out in the open, not inside of glass.
+        if let Some(scatter) = scene
+            .atmosphere
+            .as_ref()
+            .filter(|_| media.is_empty())
+            .and_then(|atmosphere| atmosphere.scatter(ray, 0.001..t_end))
+        {
+            return scatter.attenuation
+                * Self::ray_color(&scatter.scattered, scene, media, depth_remaining - 1);
+        }
+
+        let Some(mut hit_record) = hit else {
+            return scene.background.color(ray);
+        };
+
+        let object = hit_record.material.clone().unwrap();
+        let object = object.borrow();
+        let (material, transmitted) = match media.cross(&hit_record) {
+            // where two objects overlap, e.g. a liquid inside of the walls of
+            // its glass. Just keep going like the surface isn't there.
+            Some(Crossing::Ignored(next)) => {
+                let through = Ray {
+                    origin: hit_record.p,
+                    direction: ray.direction,
+                    ray_type: ray.ray_type,
+                };
+                return Self::ray_color(&through, scene, &next, depth_remaining);
             }
-        } else {
-            let unit_direction: Vec3 = ray.direction.unit_vec();
-            // normalize this to a range of 0 and 1
-            let a = 0.5 * (unit_direction.y + 1.0);
-            // linear interpolation of a with an off-blue color
-            (1.0 - a) * vec3![1.0, 1.0, 1.0] + a * vec3!(0.5, 0.7, 1.0)
+            Some(Crossing::Boundary {
+                material,
+                transmitted,
+            }) => (Cow::Owned(material), Some(transmitted)),
+            None => (Cow::Borrowed(&*object), None),
+        };
+
+        // lights don't bounce anything, they just add their own light on
+        // top of whatever got bounced
+        let emitted = emitted(&material, &hit_record);
+
+        if let Some(normal_map) = &material.normal_map {
+            perturb_normal(normal_map, &mut hit_record);
         }
+
+        // now move everything to a range of 0 to 1 and return the color
+        let Some(sample) = sample(&material, &ray.direction, &hit_record, scene) else {
+            return emitted;
+        };
+
+        // a bent normal can ask for a bounce that goes through the real
+        // surface (or a refraction that doesn't), which would leak light
+        // through the object. The shading and geometric normals have to
+        // agree on which side the new ray is on, otherwise we drop it.
+        let direction = sample.scattered.direction;
+        let shading_side = direction.dot(hit_record.normal) > 0.;
+        let geometric_side = direction.dot(hit_record.geometric_normal) > 0.;
+        if shading_side != geometric_side {
+            return emitted;
+        }
+
+        // the geometric normal faces the ray, so anything going the other way
+        // went through the surface
+        let media = match transmitted {
+            Some(ref transmitted) if !geometric_side => transmitted,
+            _ => media,
+        };
+
+        // each bounce reduces light, attenuation / power droppoff / bounces away
+        emitted
+            + sample.weight * Self::ray_color(&sample.scattered, scene, media, depth_remaining - 1)
     }
 }
 
@@ -177,6 +261,20 @@ pub fn random_unit_vector() -> Vec3 {
     random_vec_in_sphere().unit_vec()
 }
 
+/// Random direction in *local* space (normal is +z), where directions closer
+/// to the normal are more likely, exactly as much more likely as lambert's
+/// cosine law says (so pdf = cos(theta) / pi). Pick a point on the unit disk
+/// and push it straight up onto the hemisphere.
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_double(0.0..1.0);
+    let r2 = random_double(0.0..1.0);
+
+    let phi = 2. * PI * r1;
+    let r = r2.sqrt();
+
+    vec3![r * phi.cos(), r * phi.sin(), (1. - r2).sqrt()]
+}
+
 fn random_vec_in_sphere() -> Vec3 {
     loop {
         let v = random_vec_in_cube();
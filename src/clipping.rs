@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Material, MaterialType};
+use crate::ray::Ray;
+use crate::texture::solid;
+use crate::vec::*;
+use crate::vec3;
+use crate::Object;
+
+/// How far past a clipped hit we start looking again. Has to be bigger than 0,
+/// otherwise we'd just find the exact same hit over and over
+const CLIP_EPSILON: f64 = 1e-6;
+
+/// A plane that cuts away all geometry on the side that its normal points to.
+///
+/// Used for section views, i.e. slicing a model in half to look inside of it.
+/// Planes can live on the scene (cuts everything) or on a single object.
+#[derive(Debug, Clone)]
+pub struct ClippingPlane {
+    pub point: Point3,
+    pub normal: Vec3,
+    /// If set, the hole that the plane cuts into a closed object gets filled
+    /// in with a flat surface of this color. Otherwise you can see straight
+    /// into the (hollow) inside of the object.
+    pub cap: Option<Color>,
+}
+
+impl ClippingPlane {
+    /// Whether or not `p` is on the side of the plane that gets cut away
+    pub fn clips(&self, p: Point3) -> bool {
+        (p - self.point).dot(self.normal) > 0.
+    }
+
+    /// The `t` at which the ray crosses the plane, if it ever does
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let denominator = ray.direction.dot(self.normal);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        Some((self.point - ray.origin).dot(self.normal) / denominator)
+    }
+
+    /// Clipping box, which is just six planes facing outwards. Since a point
+    /// gets clipped if *any* plane clips it, only what's inside the box is
+    /// kept.
+    pub fn section_box(min: Point3, max: Point3, cap: Option<Color>) -> Vec<ClippingPlane> {
+        [
+            (min, vec3![-1., 0., 0.]),
+            (min, vec3![0., -1., 0.]),
+            (min, vec3![0., 0., -1.]),
+            (max, vec3![1., 0., 0.]),
+            (max, vec3![0., 1., 0.]),
+            (max, vec3![0., 0., 1.]),
+        ]
+        .into_iter()
+        .map(|(point, normal)| ClippingPlane { point, normal, cap })
+        .collect()
+    }
+}
+
+/// The part of the ray that's on the kept side of every plane, as a range of
+/// `t`s (the kept part is convex, so it's always one piece), along with the
+/// plane that the ray comes into it through. `None` if the whole ray gets
+/// clipped.
+fn kept_range<'a>(
+    planes: &[&'a ClippingPlane],
+    ray: &Ray,
+) -> Option<(Range<f64>, Option<&'a ClippingPlane>)> {
+    let mut kept = f64::NEG_INFINITY..f64::INFINITY;
+    let mut entering = None;
+
+    for &plane in planes {
+        let Some(t) = plane.intersect(ray) else {
+            // parallel to the plane, so the ray is on one side of it the
+            // whole way
+            if plane.clips(ray.origin) {
+                return None;
+            }
+            continue;
+        };
+
+        if ray.direction.dot(plane.normal) < 0. {
+            // going into the kept side
+            if t > kept.start {
+                kept.start = t;
+                entering = Some(plane);
+            }
+        } else {
+            kept.end = kept.end.min(t);
+        }
+    }
+
+    (kept.start <= kept.end).then_some((kept, entering))
+}
+
+/// Same as `Object::hit`, but skips over any hits that are on the clipped side
+/// of one of `planes`, and keeps searching along the ray behind them.
+///
+/// Capping works because our objects are closed: we walk over every surface
+/// along the ray (clipped or not), and the ray is inside of the solid whenever
+/// the next surface is a back face. If it goes into the kept part of the
+/// object while it's inside, the plane that it went in through gets capped.
+pub fn hit_clipped(
+    object: &Object,
+    planes: &[&ClippingPlane],
+    ray: &Ray,
+    ray_range: Range<f64>,
+) -> Option<HitRecord> {
+    let (kept, entering) = kept_range(planes, ray)?;
+    let mut start = ray_range.start;
+
+    loop {
+        // past the end of the range too, we still need to know if we're inside
+        let next = Object::hit(ray, start..f64::INFINITY, &object.object_type);
+        let inside = next
+            .as_ref()
+            .is_some_and(|hit_record| !hit_record.front_face);
+        let end = next
+            .as_ref()
+            .map_or(f64::INFINITY, |hit_record| hit_record.t)
+            .min(ray_range.end);
+
+        if inside && start < kept.start && kept.start < end {
+            if let Some(cap) = entering.and_then(|plane| cap_hit(plane, ray, kept.start)) {
+                return Some(cap);
+            }
+        }
+
+        let mut hit_record = next?;
+        if hit_record.t >= ray_range.end {
+            return None;
+        }
+        if !planes.iter().any(|plane| plane.clips(hit_record.p)) {
+            hit_record.material = Some(object.material.clone());
+            return Some(hit_record);
+        }
+        start = hit_record.t + CLIP_EPSILON;
+    }
+}
+
+/// Where the ray goes through `plane`'s cap, if it has one
+fn cap_hit(plane: &ClippingPlane, ray: &Ray, t: f64) -> Option<HitRecord> {
+    let cap = plane.cap?;
+
+    Some(HitRecord::new(
+        ray.at(t),
+        t,
+        // the cap faces out towards the part that got cut away
+        plane.normal.unit_vec(),
+        ray,
+        Some(Rc::new(RefCell::new(Material {
+            albedo: solid(cap),
+            material_type: MaterialType::Diffuse,
+            normal_map: None,
+            thin_film: None,
+        }))),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ObjectType, Scene, Visibility};
+
+    /// A radius 1 sphere at `0, 0, -5`, cut by `planes`
+    fn scene(planes: Vec<ClippingPlane>) -> Scene {
+        let mut scene = Scene::default();
+        scene.add(Object {
+            hit_record: None,
+            object_type: ObjectType::Sphere {
+                radius: 1.,
+                center: vec3![0., 0., -5.],
+            },
+            material: Rc::new(RefCell::new(Material {
+                albedo: solid(vec3![1., 1., 1.]),
+                material_type: MaterialType::Diffuse,
+                normal_map: None,
+                thin_film: None,
+            })),
+            label: "sphere".to_string(),
+            visibility: Visibility::default(),
+            clipping_planes: planes,
+        });
+        scene
+    }
+
+    /// Shot from the origin towards `-z`, `x` to the side of the sphere's
+    /// center
+    fn hit(scene: &mut Scene, x: f64) -> Option<HitRecord> {
+        let ray = Ray {
+            origin: vec3![x, 0., 0.],
+            direction: vec3![0., 0., -1.],
+            ray_type: Default::default(),
+        };
+        scene.hit(&ray, 0.001..f64::INFINITY)
+    }
+
+    fn color(hit_record: &HitRecord) -> Color {
+        let material = hit_record.material.as_ref().unwrap().borrow();
+        material.albedo.value(0., 0., &hit_record.p)
+    }
+
+    #[test]
+    fn single_plane_gets_capped() {
+        let red = vec3![1., 0., 0.];
+        let mut capped = scene(vec![ClippingPlane {
+            point: vec3![0., 0., -5.],
+            normal: vec3![0., 0., 1.],
+            cap: Some(red),
+        }]);
+        let cap = hit(&mut capped, 0.).unwrap();
+        assert!((cap.t - 5.).abs() < 1e-9);
+        assert!(cap.front_face);
+        assert_eq!(color(&cap), red);
+
+        // without a cap we see the inside of the back of the sphere
+        let mut open = scene(vec![ClippingPlane {
+            point: vec3![0., 0., -5.],
+            normal: vec3![0., 0., 1.],
+            cap: None,
+        }]);
+        let back = hit(&mut open, 0.).unwrap();
+        assert!((back.t - 6.).abs() < 1e-9);
+        assert!(!back.front_face);
+
+        // a plane that only cuts the back away doesn't change the front
+        let mut behind = scene(vec![ClippingPlane {
+            point: vec3![0., 0., -5.5],
+            normal: vec3![0., 0., -1.],
+            cap: Some(red),
+        }]);
+        let front = hit(&mut behind, 0.).unwrap();
+        assert!((front.t - 4.).abs() < 1e-9);
+        assert_eq!(color(&front), vec3![1., 1., 1.]);
+    }
+
+    #[test]
+    fn section_box_inside_of_the_object_gets_capped() {
+        let red = vec3![1., 0., 0.];
+        let mut scene = scene(ClippingPlane::section_box(
+            vec3![-0.2, -0.2, -5.2],
+            vec3![0.2, 0.2, -4.8],
+            Some(red),
+        ));
+
+        // every surface of the sphere along the ray is clipped, all that's
+        // left is the front of the box
+        let cap = hit(&mut scene, 0.).unwrap();
+        assert!((cap.t - 4.8).abs() < 1e-9);
+        assert!(cap.front_face);
+        assert_eq!(cap.normal, vec3![0., 0., 1.]);
+        assert_eq!(color(&cap), red);
+
+        // and next to the box there's nothing left
+        assert!(hit(&mut scene, 0.5).is_none());
+    }
+}
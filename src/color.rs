@@ -38,6 +38,12 @@ pub fn write_color(
     Ok(())
 }
 
+/// How bright a color looks to us, as a single number. Our eyes are way more
+/// sensitive to green than to blue.
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 fn linear_to_gamma(linear_component: f64) -> f64 {
     linear_component.sqrt()
 }
\ No newline at end of file
@@ -1,11 +1,13 @@
 use std::cell::RefCell;
+use std::f64::consts::PI;
 use std::ops::Range;
 use std::rc::Rc;
 
 use crate::material::Material;
-use crate::material::MaterialType;
 use crate::ray::*;
 use crate::vec::*;
+use crate::vec3;
+use crate::volume::{hit_constant_medium, hit_grid_medium};
 use crate::Object;
 use crate::ObjectType;
 
@@ -15,13 +17,25 @@ pub struct HitRecord {
     pub p: Point3,
     /// The normal of the surface at the point of intersection
     /// - We decided to always point it against the ray, and this is calculated
-    /// at "geometry" (i.e. ray bouncing) time
+    ///   at "geometry" (i.e. ray bouncing) time
+    /// - This is the *shading* normal, so normal/bump maps can bend it away from
+    ///   the real surface
     pub normal: Vec3,
+    /// The true normal of the surface, also pointing against the ray. Never
+    /// gets perturbed, so we can tell when a bent `normal` would send a ray
+    /// through the surface.
+    pub geometric_normal: Vec3,
+    /// Unit vector along the surface in the direction that `u` increases, used
+    /// to orient tangent space normal maps
+    pub tangent: Vec3,
     /// The `t` multiplier of the ray's direction vector
     pub t: f64,
     /// Whether or not the ray hit the object's surface from the outside or from
     /// the inside
     pub front_face: bool,
+    /// Surface coordinates of the hit, both from 0 to 1, used for textures
+    pub u: f64,
+    pub v: f64,
     pub material: Option<Rc<RefCell<Material>>>,
 }
 
@@ -39,8 +53,12 @@ impl HitRecord {
             t,
             p,
             normal,
+            geometric_normal: normal,
+            tangent: outward_normal.any_perpendicular(),
             front_face,
-            material
+            u: 0.,
+            v: 0.,
+            material,
         }
     }
 
@@ -64,6 +82,16 @@ impl Hittable for Object {
     fn hit(ray: &Ray, ray_range: Range<f64>, object_type: &ObjectType) -> Option<HitRecord> {
         match *object_type {
             ObjectType::Sphere { radius, center } => hit_sphere(center, radius, ray, ray_range),
+            ObjectType::ConstantMedium {
+                ref boundary,
+                density,
+            } => hit_constant_medium(boundary, density, ray, ray_range),
+            ObjectType::GridMedium {
+                ref grid,
+                min,
+                max,
+                density,
+            } => hit_grid_medium(grid, min, max, density, ray, ray_range),
         }
     }
 }
@@ -125,13 +153,38 @@ pub fn hit_sphere(
         // the normal of a sphere is radiated out from the center to
         // the intersection point always
         let outward_normal = (-center + p) / radius;
+        let (u, v) = sphere_uv(&outward_normal);
+        // the direction u goes in, i.e. around the y axis. Straight up and down
+        // at the poles there isn't one, so just use whatever `new` picked
+        let tangent = vec3![outward_normal.z, 0., -outward_normal.x];
 
-        Some(HitRecord::new(p, t, outward_normal, ray, None))
+        let hit_record = HitRecord::new(p, t, outward_normal, ray, None);
+        Some(HitRecord {
+            u,
+            v,
+            tangent: if tangent.len_squared() > 1e-12 {
+                tangent.unit_vec()
+            } else {
+                hit_record.tangent
+            },
+            ..hit_record
+        })
     } else {
         None
     }
 }
 
+/// `p` is a point on the unit sphere (i.e. the outward normal). We wrap the
+/// texture around the sphere like a globe:
+/// - u goes around the y axis, starting from -x, (longitude)
+/// - v goes from the bottom (-y) to the top (+y) (latitude)
+pub fn sphere_uv(p: &Point3) -> (f64, f64) {
+    let theta = (-p.y).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+
+    (phi / (2. * PI), theta / PI)
+}
+
 /// Instead of calculating on demand for if a ray intersects an object, we
 /// simply store hit info for all objects for a specific ray (potentially lots
 /// of mutations)
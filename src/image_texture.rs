@@ -0,0 +1,322 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::color::Color;
+use crate::texture::Texture;
+use crate::vec::*;
+use crate::vec3;
+
+/// A bitmap that's already been decoded (see `ColorSpace`), stored row by row
+/// starting from the *top* left (same as ppm/png).
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+// printing out every single pixel isn't useful to anyone
+impl fmt::Debug for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Image")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How the numbers in an image file should be read
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Photos and painted color maps, which get converted to linear
+    #[default]
+    Srgb,
+    /// Normal maps, bump maps, roughness maps, etc. The numbers in these aren't
+    /// colors, so we leave them alone
+    Linear,
+}
+
+impl ColorSpace {
+    /// Turns a 0 to 1 value from the file into a linear one
+    fn decode(&self, c: f64) -> f64 {
+        match self {
+            ColorSpace::Srgb => srgb_to_linear(c),
+            ColorSpace::Linear => c,
+        }
+    }
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+impl Image {
+    /// Loads a ppm (`P3` or `P6`, i.e. what we write out) or a png. We go by
+    /// the contents of the file rather than the extension.
+    pub fn load(path: impl AsRef<Path>, color_space: ColorSpace) -> Result<Image, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+
+        if bytes.starts_with(&PNG_SIGNATURE) {
+            Image::from_png(&bytes, color_space)
+        } else {
+            Image::from_ppm(&bytes, color_space)
+        }
+    }
+
+    pub fn from_ppm(bytes: &[u8], color_space: ColorSpace) -> Result<Image, Box<dyn Error>> {
+        let mut reader = PpmReader { bytes, position: 0 };
+
+        let magic = reader.token()?;
+        let width: usize = reader.token()?.parse()?;
+        let height: usize = reader.token()?.parse()?;
+        let max_value: u32 = reader.token()?.parse()?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(format!("invalid ppm max value {max_value}").into());
+        }
+
+        // the header could say anything, don't let it overflow
+        let sample_count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or_else(|| format!("ppm is too big ({width}x{height})"))?;
+
+        let samples = match magic.as_str() {
+            "P3" => (0..sample_count)
+                .map(|_| Ok(reader.token()?.parse::<u32>()?))
+                .collect::<Result<Vec<u32>, Box<dyn Error>>>()?,
+            "P6" => {
+                // exactly one whitespace character separates the header from
+                // the binary data
+                let data = bytes
+                    .get(reader.position + 1..)
+                    .ok_or("ppm ended before the pixel data")?;
+                // each sample is 2 bytes (big endian) if they don't fit in one
+                let sample_size = if max_value < 256 { 1 } else { 2 };
+                let data_size = sample_count
+                    .checked_mul(sample_size)
+                    .ok_or_else(|| format!("ppm is too big ({width}x{height})"))?;
+                if data.len() < data_size {
+                    return Err("ppm ended before the pixel data".into());
+                }
+                data.chunks_exact(sample_size)
+                    .take(sample_count)
+                    .map(|sample| match sample {
+                        [value] => *value as u32,
+                        [high, low] => u16::from_be_bytes([*high, *low]) as u32,
+                        _ => unreachable!(),
+                    })
+                    .collect()
+            }
+            _ => return Err(format!("unsupported ppm format {magic}").into()),
+        };
+
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|rgb| {
+                let channel = |c: u32| color_space.decode(c as f64 / max_value as f64);
+                vec3![channel(rgb[0]), channel(rgb[1]), channel(rgb[2])]
+            })
+            .collect();
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn from_png(bytes: &[u8], color_space: ColorSpace) -> Result<Image, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(bytes);
+        // palettes get expanded to rgb and 16 bit gets cut down to 8 bit, so
+        // we only have to deal with the 4 color types below
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let buf = &buf[..info.buffer_size()];
+
+        let channel = |c: u8| color_space.decode(c as f64 / 255.);
+        // alpha just gets dropped, we don't do transparency
+        let pixels = match info.color_type {
+            png::ColorType::Grayscale => buf
+                .iter()
+                .map(|&g| vec3![channel(g), channel(g), channel(g)])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .map(|ga| vec3![channel(ga[0]), channel(ga[0]), channel(ga[0])])
+                .collect(),
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .map(|rgb| vec3![channel(rgb[0]), channel(rgb[1]), channel(rgb[2])])
+                .collect(),
+            png::ColorType::Rgba => buf
+                .chunks_exact(4)
+                .map(|rgba| vec3![channel(rgba[0]), channel(rgba[1]), channel(rgba[2])])
+                .collect(),
+            png::ColorType::Indexed => return Err("png palette wasn't expanded".into()),
+        };
+
+        Ok(Image {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Walks through the whitespace separated tokens of a ppm, skipping `#`
+/// comments
+struct PpmReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl PpmReader<'_> {
+    fn token(&mut self) -> Result<String, Box<dyn Error>> {
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.bytes.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err("unexpected end of ppm".into()),
+            }
+        }
+
+        let start = self.position;
+        while matches!(self.bytes.get(self.position), Some(c) if !c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+
+        Ok(String::from_utf8_lossy(&self.bytes[start..self.position]).into_owned())
+    }
+}
+
+/// Images are stored in sRGB (so that more of the 0-255 range goes to dark
+/// colors, which our eyes are better at telling apart), but all of our light
+/// math is linear, so we have to undo that
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// What to do when a uv lands outside of 0 to 1
+#[derive(Debug, Default, Clone, Copy)]
+pub enum WrapMode {
+    /// Tile the image over and over
+    #[default]
+    Repeat,
+    /// Stretch out the pixels on the edge
+    Clamp,
+    /// Tile the image, but flip every other copy so the seams line up
+    Mirror,
+}
+
+impl WrapMode {
+    /// Brings a pixel index back inside of `0..size`
+    fn wrap(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum Filter {
+    /// Just grab whichever pixel we land in, blocky up close
+    Nearest,
+    /// Blend the 4 closest pixels based on how close we are to each
+    #[default]
+    Bilinear,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    pub image: Rc<Image>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl ImageTexture {
+    /// For color maps, see `ColorSpace::Srgb`
+    pub fn load(path: impl AsRef<Path>) -> Result<ImageTexture, Box<dyn Error>> {
+        Self::load_with(path, ColorSpace::Srgb)
+    }
+
+    /// For normal/bump maps and anything else that isn't a color, see
+    /// `ColorSpace::Linear`
+    pub fn load_data(path: impl AsRef<Path>) -> Result<ImageTexture, Box<dyn Error>> {
+        Self::load_with(path, ColorSpace::Linear)
+    }
+
+    fn load_with(
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+    ) -> Result<ImageTexture, Box<dyn Error>> {
+        Ok(ImageTexture {
+            image: Rc::new(Image::load(path, color_space)?),
+            wrap: WrapMode::default(),
+            filter: Filter::default(),
+        })
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        self.image.pixel(
+            self.wrap.wrap(x, self.image.width),
+            self.wrap.wrap(y, self.image.height),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.image.pixels.is_empty() {
+            // bright magenta, so a broken texture is obvious
+            return vec3![1., 0., 1.];
+        }
+
+        // v goes up but image rows go down
+        let x = u * self.image.width as f64;
+        let y = (1. - v) * self.image.height as f64;
+
+        match self.filter {
+            Filter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {
+                // pixel centers are at +0.5, so shift so that the integer
+                // part is the top left of the 4 pixels around us
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = (1. - tx) * self.texel(x0, y0) + tx * self.texel(x0 + 1, y0);
+                let bot = (1. - tx) * self.texel(x0, y0 + 1) + tx * self.texel(x0 + 1, y0 + 1);
+                (1. - ty) * top + ty * bot
+            }
+        }
+    }
+}
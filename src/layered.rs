@@ -0,0 +1,131 @@
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{scatter, Material, Scatter};
+use crate::microfacet::{sample_rough_dielectric, Ggx};
+use crate::ray::{Ray, RayType};
+use crate::vec::*;
+use crate::{vec3, Scene};
+
+/// How many times light can bounce back and forth between the coat and the
+/// base before we give up on it
+const MAX_INTERNAL_BOUNCES: u32 = 8;
+
+/// A clear (or tinted) dielectric layer sitting on top of another material,
+/// like the lacquer on car paint or the varnish on wood.
+#[derive(Debug, Clone)]
+pub struct Coat {
+    pub ior: f64,
+    /// 0 is a perfectly glossy coat, 1 is completely rough
+    pub roughness: f64,
+    /// How much of each color makes it through the coat going straight down
+    /// (and then the same again coming back up). Light going through at an
+    /// angle travels further, so it gets tinted more. White is perfectly
+    /// clear.
+    pub absorption: Color,
+}
+
+impl Coat {
+    /// Light passing through the coat at `cos_theta` from the normal
+    fn transmittance(&self, cos_theta: f64) -> Color {
+        let distance = 1. / cos_theta.abs().max(1e-4);
+        vec3![
+            self.absorption.x.powf(distance),
+            self.absorption.y.powf(distance),
+            self.absorption.z.powf(distance)
+        ]
+    }
+}
+
+/// Flip a local direction upside down, so that we can treat the underside of
+/// the coat like any other interface (with its normal pointing at us)
+fn flip(w: Vec3) -> Vec3 {
+    vec3![w.x, w.y, -w.z]
+}
+
+/// Follows light through the layers with a little random walk:
+/// 1. hit the coat from outside, it either reflects (and we're done) or
+///    refracts into the coat
+/// 2. get tinted on the way down, bounce off of the base (with whatever the
+///    base's own `scatter` does), get tinted on the way up
+/// 3. hit the underside of the coat, where it either gets out or gets
+///    reflected back down to the base, and we go back to 2
+///
+/// Every choice is made with the fresnel of the interface (see
+/// `sample_rough_dielectric`), so it's the same as picking a layer at random
+/// with the probability that light actually ends up there.
+pub fn scatter_layered(
+    coat: &Coat,
+    base: &Material,
+    ray_incident: &Vec3,
+    hit_record: &HitRecord,
+    scene: &mut Scene,
+) -> Option<Scatter> {
+    // the coat is on the outside, so from the inside of the object we just see
+    // the base
+    if !hit_record.front_face {
+        return scatter(base, ray_incident, hit_record, scene);
+    }
+
+    let frame = Onb::new(hit_record.normal, hit_record.tangent);
+    let ggx = Ggx::isotropic(coat.roughness);
+
+    let wo = frame.to_local(-ray_incident.unit_vec());
+    if wo.z <= 0. {
+        return None;
+    }
+
+    let mut w = sample_rough_dielectric(&ggx, wo, coat.ior)?;
+    let mut throughput = ggx.g2(wo, w) / ggx.g1(wo) * vec3![1., 1., 1.];
+
+    if w.z > 0. {
+        // just a reflection off of the coat
+        return Some(Scatter {
+            scattered: Ray {
+                origin: hit_record.p,
+                direction: frame.to_world(w),
+                ray_type: RayType::Specular,
+            },
+            attenuation: throughput,
+        });
+    }
+
+    for _ in 0..MAX_INTERNAL_BOUNCES {
+        // down through the coat and off of the base
+        throughput = throughput * coat.transmittance(w.z);
+        let base_scatter = scatter(base, &frame.to_world(w), hit_record, scene)?;
+        throughput = throughput * base_scatter.attenuation;
+        let ray_type = base_scatter.scattered.ray_type;
+
+        let up = frame.to_local(base_scatter.scattered.direction.unit_vec());
+        if up.z <= 0. {
+            // the base let the light through (e.g. glass), it's not coming
+            // back up to the coat
+            return Some(Scatter {
+                attenuation: throughput,
+                ..base_scatter
+            });
+        }
+
+        // back up through the coat and out of its underside, looking at it
+        // from below
+        throughput = throughput * coat.transmittance(up.z);
+        let wo_inside = -flip(up);
+        let wi_inside = sample_rough_dielectric(&ggx, wo_inside, 1. / coat.ior)?;
+        throughput = ggx.g2(wo_inside, wi_inside) / ggx.g1(wo_inside) * throughput;
+
+        w = flip(wi_inside);
+        if w.z > 0. {
+            return Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction: frame.to_world(w),
+                    ray_type,
+                },
+                attenuation: throughput,
+            });
+        }
+        // otherwise the underside of the coat reflected it back down
+    }
+
+    None
+}
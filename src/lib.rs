@@ -1,9 +1,22 @@
 pub mod camera;
+pub mod clipping;
 pub mod color;
 pub mod hittable;
+pub mod image_texture;
+pub mod layered;
 pub mod material;
+pub mod media;
+pub mod microfacet;
+pub mod noise;
+pub mod normal_map;
+pub mod principled;
 pub mod ray;
+pub mod subsurface;
+pub mod texture;
+pub mod thin_film;
 pub mod vec;
+pub mod volume;
+pub mod voxel_grid;
 
 pub use std::error::Error;
 use std::{
@@ -12,29 +25,127 @@ use std::{
     rc::Rc,
 };
 
-use hittable::{HitRecord, Hittable};
-use material::{Material, MaterialType};
+use clipping::{hit_clipped, ClippingPlane};
+use color::Color;
+use hittable::HitRecord;
+use material::Material;
 use rand::{distributions::uniform::SampleRange, thread_rng, Rng};
-use ray::Ray;
-use vec::Vec3;
+use ray::{Ray, RayType};
+use vec::{Point3, Vec3};
+use volume::Atmosphere;
+use voxel_grid::VoxelGrid;
 
 pub struct Object {
     pub hit_record: Option<HitRecord>,
     pub object_type: ObjectType,
     pub material: Rc<RefCell<Material>>,
-    pub label: String
+    pub label: String,
+    pub visibility: Visibility,
+    /// Planes that only cut this object, on top of the scene's planes
+    pub clipping_planes: Vec<ClippingPlane>,
+}
+
+/// Flags for which kinds of rays are allowed to hit an object. A ray that
+/// isn't allowed to see an object just passes straight through it, as if it
+/// wasn't in the scene at all.
+///
+/// e.g. an "invisible light card" is `camera: false`. There are no shadow rays
+/// in this tracer, light is only ever found by bouncing into it, so something
+/// that "doesn't cast shadows" is `diffuse: false` (which also means it
+/// doesn't bounce any light onto what's around it).
+#[derive(Debug, Clone, Copy)]
+pub struct Visibility {
+    pub camera: bool,
+    /// reflection and refraction rays
+    pub specular: bool,
+    pub diffuse: bool,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self {
+            camera: true,
+            specular: true,
+            diffuse: true,
+        }
+    }
+}
+
+impl Visibility {
+    pub fn sees(&self, ray_type: RayType) -> bool {
+        match ray_type {
+            RayType::Camera => self.camera,
+            RayType::Specular => self.specular,
+            RayType::Diffuse => self.diffuse,
+            RayType::Subsurface => true,
+        }
+    }
 }
 
 pub enum ObjectType {
-    Sphere { radius: f64, center: Vec3 },
+    Sphere {
+        radius: f64,
+        center: Vec3,
+    },
+    /// Smoke or fog filling up the inside of `boundary`, which has to be
+    /// closed. Bigger `density` is thicker. Use it with a
+    /// `MaterialType::Volume`. See `hit_constant_medium`.
+    ConstantMedium {
+        boundary: Box<ObjectType>,
+        density: f64,
+    },
+    /// Smoke or clouds that are thicker in some places than others, from a
+    /// `VoxelGrid` stretched over the box from `min` to `max`. The grid's
+    /// values get multiplied by `density`. Use it with a
+    /// `MaterialType::Volume`. See `hit_grid_medium`.
+    GridMedium {
+        grid: Rc<VoxelGrid>,
+        min: Point3,
+        max: Point3,
+        density: f64,
+    },
 }
 
 pub fn lerp(start: Vec3, end: Vec3, x: f64) -> Vec3 {
     (1. - x) * start + x * end
 }
 
+#[derive(Default)]
 pub struct Scene {
     pub objects: Vec<Rc<RefCell<Object>>>,
+    /// Planes that cut every object in the scene
+    pub clipping_planes: Vec<ClippingPlane>,
+    /// What a ray sees when it doesn't hit anything
+    pub background: Background,
+    /// Fog filling up the whole scene, see `Atmosphere`
+    pub atmosphere: Option<Atmosphere>,
+}
+
+/// The light coming from "infinitely far away", i.e. for rays that escape the
+/// scene.
+#[derive(Debug, Default, Clone)]
+pub enum Background {
+    /// The blue-white gradient from the book
+    #[default]
+    Sky,
+    /// A flat color. Use black for closed rooms (e.g. a cornell box), where all
+    /// of the light should come from emissive objects.
+    Solid(Color),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_direction: Vec3 = ray.direction.unit_vec();
+                // normalize this to a range of 0 and 1
+                let a = 0.5 * (unit_direction.y + 1.0);
+                // linear interpolation of a with an off-blue color
+                (1.0 - a) * vec3![1.0, 1.0, 1.0] + a * vec3!(0.5, 0.7, 1.0)
+            }
+            Background::Solid(color) => *color,
+        }
+    }
 }
 impl Scene {
     pub fn add(&mut self, object: Object) {
@@ -50,11 +161,19 @@ impl Scene {
 
         // # of objects is usually small for our raytracer
         self.objects.iter().for_each(|o| {
-            if let Some(mut t_hit_record) =
-                Object::hit(ray, ray_range.clone(), &o.borrow().object_type)
-            {
+            let o = o.borrow();
+            if !o.visibility.sees(ray.ray_type) {
+                return;
+            }
+
+            let planes: Vec<&ClippingPlane> = self
+                .clipping_planes
+                .iter()
+                .chain(o.clipping_planes.iter())
+                .collect();
+
+            if let Some(t_hit_record) = hit_clipped(&o, &planes, ray, ray_range.clone()) {
                 ray_range.end = t_hit_record.t;
-                t_hit_record.material = Some(o.borrow().material.clone());
                 // the bigger the t is, the farther away the object. Therefore
                 // to only accept the closest object we should cap the t value
                 // at the current t
@@ -4,19 +4,17 @@ const VIEWPORT_HEIGHT: f64 = 2.;
 // const IMAGE_HEIGHT: u32 = 256;
 
 use std::cell::RefCell;
-use std::ops::RangeBounds;
 use std::rc::Rc;
 
-use rand::distributions::uniform::SampleRange;
-use rand::{thread_rng, Rng};
 use raytracing_in_one_weekend::camera::*;
 use raytracing_in_one_weekend::material::{Material, MaterialType};
+use raytracing_in_one_weekend::texture::solid;
 use raytracing_in_one_weekend::*;
 
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    let mut scene = Scene { objects: vec![] };
+    let mut scene = Scene::default();
 
     // center diffuse
     scene.add(Object {
@@ -26,10 +24,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
         hit_record: None,
         material: Rc::new(RefCell::new(Material {
-            albedo: vec3![0.7, 0.3, 0.3],
+            albedo: solid(vec3![0.7, 0.3, 0.3]),
             material_type: MaterialType::Diffuse,
+            normal_map: None,
+            thin_film: None,
         })),
         label: "center".to_string(),
+        visibility: Visibility::default(),
+        clipping_planes: vec![],
     });
 
     // bot diffuse
@@ -40,10 +42,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
         hit_record: None,
         material: Rc::new(RefCell::new(Material {
-            albedo: vec3![0.8, 0.8, 0.0],
+            albedo: solid(vec3![0.8, 0.8, 0.0]),
             material_type: MaterialType::Diffuse,
+            normal_map: None,
+            thin_film: None,
         })),
         label: "bot".to_string(),
+        visibility: Visibility::default(),
+        clipping_planes: vec![],
     });
 
     // two right left metal spheres
@@ -54,10 +60,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
         hit_record: None,
         material: Rc::new(RefCell::new(Material {
-            albedo: vec3![0.8, 0.8, 0.8],
-            material_type: MaterialType::Metal,
+            albedo: solid(vec3![0.8, 0.8, 0.8]),
+            material_type: MaterialType::metal(),
+            normal_map: None,
+            thin_film: None,
         })),
         label: "left".to_string(),
+        visibility: Visibility::default(),
+        clipping_planes: vec![],
     });
 
     scene.add(Object {
@@ -69,10 +79,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         material: Rc::new(RefCell::new(Material {
             // interesting that a gold color has less of a "pure" reflectance
             // than something like silver or steel
-            albedo: vec3![0.8, 0.6, 0.2],
-            material_type: MaterialType::Metal,
+            albedo: solid(vec3![0.8, 0.6, 0.2]),
+            material_type: MaterialType::metal(),
+            normal_map: None,
+            thin_film: None,
         })),
         label: "right".to_string(),
+        visibility: Visibility::default(),
+        clipping_planes: vec![],
     });
 
     // // return a random f64
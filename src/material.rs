@@ -1,9 +1,29 @@
+use std::f64::consts::PI;
+use std::rc::Rc;
+
 use crate::{
-    camera::{random_on_hemisphere, random_unit_vector},
-    color::Color,
+    camera::{random_cosine_direction, random_on_hemisphere, random_unit_vector},
+    color::{luminance, Color},
     hittable::HitRecord,
-    ray::Ray,
-    vec::Vec3,
+    layered::{scatter_layered, Coat},
+    lerp,
+    media::Interior,
+    microfacet::{
+        eval_microfacet_reflection, eval_rough_dielectric, fresnel_conductor, fresnel_dielectric,
+        pdf_microfacet_reflection, pdf_rough_dielectric, reflect_local,
+        sample_rough_dielectric_with, ConductorPreset, Ggx,
+    },
+    normal_map::{perturb_normal, NormalMap},
+    principled::Principled,
+    random_double,
+    ray::{Ray, RayType},
+    subsurface::scatter_subsurface,
+    texture::{solid, Texture},
+    thin_film::{thin_film_reflectance, ThinFilm},
+    vec::{Onb, Vec3},
+    vec3,
+    volume::PhaseFunction,
+    Scene,
 };
 
 /// Currently, we treat every single sphere as diffuse.
@@ -31,14 +51,172 @@ use crate::{
 
 #[derive(Debug, Clone)]
 pub struct Material {
-    pub albedo: Color,
+    /// Looked up at the hit's `(u, v)` and `p` every time we scatter
+    pub albedo: Rc<dyn Texture>,
     pub material_type: MaterialType,
+    /// Bends the shading normal before we scatter, see `NormalMap`
+    pub normal_map: Option<NormalMap>,
+    /// Iridescent coating, see `ThinFilm`. Only does anything for the
+    /// materials that have a fresnel term, i.e. `Dielectric`,
+    /// `RoughDielectric` and `Conductor`.
+    pub thin_film: Option<ThinFilm>,
 }
 
 #[derive(Debug, Clone)]
 pub enum MaterialType {
     Diffuse,
-    Metal,
+    /// Rough diffuse, for things like clay, concrete and the moon. Made of tiny
+    /// lambertian v-shaped grooves, which bounce more light straight back
+    /// towards where it came from than plain lambert does (that's why the full
+    /// moon looks flat instead of like a shaded ball). `sigma` is how rough the
+    /// grooves are, in radians. 0 is the same as lambert.
+    OrenNayar {
+        sigma: f64,
+    },
+    /// A thin sheet with no inside, like a leaf, paper or a lampshade. Light
+    /// gets bounced diffusely off of either side, and some of it diffusely goes
+    /// through to the other side. `albedo` is the color it reflects.
+    Translucent {
+        /// The color that comes through out the other side
+        transmittance: Rc<dyn Texture>,
+    },
+    /// `fuzz` is how rough the metal is, from 0 (perfect mirror) to 1 (very
+    /// brushed/satin). We just nudge the reflected ray by a random vector in a
+    /// sphere of radius `fuzz`, so the bigger the sphere the blurrier.
+    Metal {
+        fuzz: f64,
+    },
+    /// Glass, water, diamond, etc. Things that both reflect and let light pass
+    /// through them. `ior` is the index of refraction of the material relative
+    /// to air. What it's surrounded by (e.g. water) is taken care of by
+    /// `MediumStack`.
+    Dielectric {
+        ior: f64,
+        interior: Interior,
+    },
+    /// A light. It gives off `albedo * strength` and doesn't bounce anything,
+    /// so strength can (and usually should) go above 1 to actually light up
+    /// the things around it.
+    Emissive {
+        strength: f64,
+    },
+    /// Physically based metal. Instead of the fuzz hack, the surface is a
+    /// bunch of tiny mirrors spread out with a GGX distribution, and how much
+    /// each one reflects comes from the fresnel equations for the metal's
+    /// complex index of refraction `(eta, k)`.
+    ///
+    /// The color comes from `eta` and `k`, so `albedo` should usually be white
+    /// (it still gets multiplied in, as a tint). See `MaterialType::conductor`
+    /// for real metals.
+    Conductor {
+        eta: Color,
+        k: Color,
+        /// 0 is a perfect mirror, 1 is completely rough
+        roughness: f64,
+    },
+    /// Brushed metal, where the scratches all go in one direction so the
+    /// highlights get stretched out across them. Same as `Conductor`, except
+    /// the roughness is different along the tangent (`roughness_x`) and
+    /// across it (`roughness_y`).
+    AnisotropicConductor {
+        eta: Color,
+        k: Color,
+        roughness_x: f64,
+        roughness_y: f64,
+        /// Spins the brushing direction around the normal, in radians
+        rotation: f64,
+        /// Per-pixel brushing direction, encoded like a normal map (rg is the
+        /// xy of the direction in tangent space). Load it with
+        /// `ImageTexture::load_data`.
+        tangent_map: Option<Rc<dyn Texture>>,
+    },
+    /// Frosted glass. Same microfacet idea as `Conductor`, but every little
+    /// facet is a tiny piece of smooth glass that can either reflect or
+    /// refract. At a roughness of 0 this is the same as `Dielectric`.
+    RoughDielectric {
+        ior: f64,
+        roughness: f64,
+        interior: Interior,
+    },
+    /// Skin, wax, marble, milk. Light goes into the object and bounces around
+    /// inside of it before coming back out, see `scatter_subsurface`. `albedo`
+    /// is how much light survives each of those bounces.
+    Subsurface {
+        /// how far light goes between bounces inside, per channel
+        mean_free_path: Color,
+        ior: f64,
+    },
+    /// The particles inside of an `ObjectType::ConstantMedium` or
+    /// `ObjectType::GridMedium`. `albedo` is how much light survives bouncing
+    /// off of one, and `phase` decides where it goes next.
+    Volume {
+        phase: PhaseFunction,
+        /// Light given off by the particles themselves, e.g. a `Blackbody`
+        /// for fire. Only particles that absorb light give any off, so it gets
+        /// scaled by `1 - albedo` (the chance that a collision is an absorption
+        /// instead of a bounce), and added every time a ray bumps into a
+        /// particle. Thicker parts glow more.
+        emission: Option<Rc<dyn Texture>>,
+    },
+    /// Disney-style uber material, where you dial in how metallic/rough/glassy
+    /// something is instead of picking a variant. Uses `albedo` as the base
+    /// color. See `Principled`.
+    Principled(Principled),
+    /// A dielectric coat on top of any other material, e.g. car paint is a
+    /// clearcoat over a metal flake base. `albedo` is ignored, the base has its
+    /// own. See `scatter_layered`.
+    Layered {
+        coat: Coat,
+        base: Box<Material>,
+    },
+    /// Two materials blended together, like rust patches on a metal or a decal
+    /// on paint. Every time we scatter, we pick one of them at random, so the
+    /// result is the average of both. `albedo` is ignored, each side has its
+    /// own.
+    Mix {
+        a: Box<Material>,
+        b: Box<Material>,
+        /// How much of `b` there is, 0 is all `a` and 1 is all `b`. It's a
+        /// texture so it can be a mask, only its brightness is used.
+        factor: Rc<dyn Texture>,
+    },
+}
+
+impl MaterialType {
+    /// A perfect mirror, i.e. `Metal` without any fuzz
+    pub fn metal() -> Self {
+        MaterialType::Metal { fuzz: 0. }
+    }
+
+    pub fn conductor(preset: ConductorPreset, roughness: f64) -> Self {
+        let (eta, k) = preset.ior();
+        MaterialType::Conductor { eta, k, roughness }
+    }
+
+    pub fn anisotropic_conductor(
+        preset: ConductorPreset,
+        roughness_x: f64,
+        roughness_y: f64,
+    ) -> Self {
+        let (eta, k) = preset.ior();
+        MaterialType::AnisotropicConductor {
+            eta,
+            k,
+            roughness_x,
+            roughness_y,
+            rotation: 0.,
+            tangent_map: None,
+        }
+    }
+
+    /// Mixes `a` and `b` by the same amount everywhere
+    pub fn mix(a: Material, b: Material, factor: f64) -> Self {
+        MaterialType::Mix {
+            a: Box::new(a),
+            b: Box::new(b),
+            factor: solid(vec3![factor, factor, factor]),
+        }
+    }
 }
 
 pub struct Scatter {
@@ -52,16 +230,115 @@ pub struct Scatter {
     pub attenuation: Color,
 }
 
+/// The material's color at the point that we hit
+fn albedo(material: &Material, hit_record: &HitRecord) -> Color {
+    material
+        .albedo
+        .value(hit_record.u, hit_record.v, &hit_record.p)
+}
+
+/// How much of `b` a `Mix` has at the point that we hit
+fn mix_factor(factor: &dyn Texture, hit_record: &HitRecord) -> f64 {
+    luminance(factor.value(hit_record.u, hit_record.v, &hit_record.p)).clamp(0., 1.)
+}
+
 fn near_zero(v: &Vec3) -> bool {
     let s = 1e-8;
 
     v.x.abs() < s && v.y.abs() < s && v.z.abs() < s
 }
 
+/// Reflectance and transmittance of a `Translucent` at a hit, and how likely
+/// we are to pick reflecting. `None` if it's completely black.
+fn translucent_split(
+    material: &Material,
+    transmittance: &dyn Texture,
+    hit_record: &HitRecord,
+) -> Option<(Color, Color, f64)> {
+    let reflectance = albedo(material, hit_record);
+    let transmittance = transmittance.value(hit_record.u, hit_record.v, &hit_record.p);
+
+    // pick a side with the probability of ending up there, same as with the
+    // fresnel on glass
+    let average = |c: Color| (c.x + c.y + c.z) / 3.;
+    let total = average(reflectance) + average(transmittance);
+    if total <= 0. {
+        return None;
+    }
+    Some((reflectance, transmittance, average(reflectance) / total))
+}
+
+/// Fresnel of a `RoughDielectric` at a hit, with the thin film if it has one
+fn dielectric_fresnel<'a>(
+    material: &'a Material,
+    ior: f64,
+    hit_record: &HitRecord,
+) -> impl Fn(f64) -> Color + 'a {
+    let (n_incident, n_transmitted) = if hit_record.front_face {
+        (1., ior)
+    } else {
+        (ior, 1.)
+    };
+    move |cos_theta_i| match &material.thin_film {
+        None => {
+            let f = fresnel_dielectric(cos_theta_i, n_transmitted / n_incident);
+            vec3![f, f, f]
+        }
+        Some(film) => thin_film_reflectance(
+            cos_theta_i,
+            n_incident,
+            film,
+            vec3![n_transmitted, n_transmitted, n_transmitted],
+            Color::default(),
+        ),
+    }
+}
+
+/// Fresnel of a metal, with the thin film if it has one
+fn conductor_fresnel(material: &Material, eta: Color, k: Color) -> impl Fn(f64) -> Color + '_ {
+    move |cos_theta_i| match &material.thin_film {
+        None => fresnel_conductor(cos_theta_i, eta, k),
+        Some(film) => thin_film_reflectance(cos_theta_i, 1., film, eta, k),
+    }
+}
+
+/// The local frame and microfacet distribution of a `Conductor` or
+/// `AnisotropicConductor`, plus its `(eta, k)`
+fn conductor_lobe(material: &Material, hit_record: &HitRecord) -> Option<(Onb, Ggx, Color, Color)> {
+    match material.material_type {
+        MaterialType::Conductor { eta, k, roughness } => Some((
+            Onb::new(hit_record.normal, hit_record.tangent),
+            Ggx::isotropic(roughness),
+            eta,
+            k,
+        )),
+        MaterialType::AnisotropicConductor {
+            eta,
+            k,
+            roughness_x,
+            roughness_y,
+            rotation,
+            ref tangent_map,
+        } => {
+            let tangent = anisotropic_tangent(rotation, tangent_map.as_deref(), hit_record);
+            Some((
+                Onb::new(hit_record.normal, tangent),
+                Ggx::anisotropic(roughness_x, roughness_y),
+                eta,
+                k,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// `scene` is only needed by materials that have to trace their own rays
+/// (subsurface)
 pub fn scatter(
     material: &Material,
     ray_incident: &Vec3,
     hit_record: &HitRecord,
+    scene: &mut Scene,
 ) -> Option<Scatter> {
     match material.material_type {
         MaterialType::Diffuse => {
@@ -74,22 +351,727 @@ pub fn scatter(
                 scattered: Ray {
                     origin: hit_record.p,
                     direction,
+                    ray_type: RayType::Diffuse,
+                },
+                attenuation: albedo(material, hit_record),
+            })
+        }
+        MaterialType::OrenNayar { sigma } => {
+            let frame = Onb::new(hit_record.normal, hit_record.tangent);
+            let wo = frame.to_local(-ray_incident.unit_vec());
+            let wi = random_cosine_direction();
+
+            // cosine sampling cancels out the cos / pi of lambert, so all
+            // that's left is oren nayar's extra factor
+            Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction: frame.to_world(wi),
+                    ray_type: RayType::Diffuse,
+                },
+                attenuation: oren_nayar(sigma, wo, wi) * albedo(material, hit_record),
+            })
+        }
+        MaterialType::Translucent { ref transmittance } => {
+            let (reflectance, transmittance, reflect_probability) =
+                translucent_split(material, transmittance.as_ref(), hit_record)?;
+
+            // the surface is infinitely thin, so going through is just
+            // lambert on the other side
+            let frame = Onb::new(hit_record.normal, hit_record.tangent);
+            let wi = random_cosine_direction();
+            let (wi, attenuation) = if random_double(0.0..1.0) < reflect_probability {
+                (wi, reflectance / reflect_probability)
+            } else {
+                (
+                    vec3![wi.x, wi.y, -wi.z],
+                    transmittance / (1. - reflect_probability),
+                )
+            };
+
+            Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction: frame.to_world(wi),
+                    ray_type: RayType::Diffuse,
+                },
+                attenuation,
+            })
+        }
+        MaterialType::Metal { fuzz } => {
+            // needs to be normalized first, otherwise the fuzz sphere would be
+            // tiny compared to a long reflected ray (or huge for a short one)
+            let direction = reflect(ray_incident, &hit_record.normal).unit_vec()
+                + fuzz.clamp(0., 1.) * random_unit_vector();
+
+            // a big enough fuzz can push the ray into the surface, so just
+            // absorb it
+            if direction.dot(hit_record.normal) <= 0. {
+                return None;
+            }
+
+            Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction,
+                    ray_type: RayType::Specular,
+                },
+                attenuation: albedo(material, hit_record),
+            })
+        }
+        MaterialType::Dielectric { ior, .. } => {
+            // the normal always faces against the ray, so if we're hitting the
+            // front face we're going from air into the material, otherwise
+            // we're going from the material back out into air
+            let refraction_ratio = if hit_record.front_face { 1. / ior } else { ior };
+
+            let unit_direction = ray_incident.unit_vec();
+            let cos_theta = (-unit_direction).dot(hit_record.normal).min(1.);
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+
+            // snell's law: sin_theta' = ratio * sin_theta, and sin can't be
+            // bigger than 1, so if it is there's no solution and everything
+            // gets reflected (total internal reflection)
+            let cannot_refract = refraction_ratio * sin_theta > 1.;
+
+            if cannot_refract {
+                return Some(Scatter {
+                    scattered: Ray {
+                        origin: hit_record.p,
+                        direction: reflect(&unit_direction, &hit_record.normal),
+                        ray_type: RayType::Specular,
+                    },
+                    attenuation: albedo(material, hit_record),
+                });
+            }
+
+            // otherwise, we reflect with a probability given by the fresnel
+            // reflectance, which goes up at grazing angles
+            let (reflected, weight) = match &material.thin_film {
+                None => (
+                    reflectance(cos_theta, refraction_ratio) > random_double(0.0..1.0),
+                    vec3![1., 1., 1.],
+                ),
+                // a thin film reflects a different amount of every color, so
+                // choose with the average and make up for it in the weight
+                Some(film) => {
+                    let (n_incident, n_transmitted) = if hit_record.front_face {
+                        (1., ior)
+                    } else {
+                        (ior, 1.)
+                    };
+                    let f = thin_film_reflectance(
+                        cos_theta,
+                        n_incident,
+                        film,
+                        vec3![n_transmitted, n_transmitted, n_transmitted],
+                        Color::default(),
+                    );
+                    let reflect_probability = (f.x + f.y + f.z) / 3.;
+
+                    if reflect_probability > random_double(0.0..1.0) {
+                        (true, f / reflect_probability)
+                    } else {
+                        (false, (vec3![1., 1., 1.] - f) / (1. - reflect_probability))
+                    }
+                }
+            };
+
+            let direction = if reflected {
+                reflect(&unit_direction, &hit_record.normal)
+            } else {
+                refract(&unit_direction, &hit_record.normal, refraction_ratio)
+            };
+
+            Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction,
+                    ray_type: RayType::Specular,
+                },
+                attenuation: weight * albedo(material, hit_record),
+            })
+        }
+        MaterialType::Emissive { .. } => None,
+        MaterialType::Conductor { .. } | MaterialType::AnisotropicConductor { .. } => {
+            let (frame, ggx, eta, k) = conductor_lobe(material, hit_record)?;
+            scatter_conductor(material, eta, k, &ggx, &frame, ray_incident, hit_record)
+        }
+        MaterialType::RoughDielectric { ior, roughness, .. } => {
+            let frame = Onb::new(hit_record.normal, hit_record.tangent);
+            let ggx = Ggx::isotropic(roughness);
+            // ior of the side we're going into over the side we're coming from
+            let eta = if hit_record.front_face { ior } else { 1. / ior };
+
+            let wo = frame.to_local(-ray_incident.unit_vec());
+            if wo.z <= 0. {
+                return None;
+            }
+
+            let (wi, fresnel_weight) = sample_rough_dielectric_with(
+                &ggx,
+                wo,
+                eta,
+                dielectric_fresnel(material, ior, hit_record),
+            )?;
+
+            Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction: frame.to_world(wi),
+                    ray_type: RayType::Specular,
+                },
+                // see `sample_rough_dielectric` for why this is the whole weight
+                attenuation: ggx.g2(wo, wi) / ggx.g1(wo)
+                    * fresnel_weight
+                    * albedo(material, hit_record),
+            })
+        }
+        MaterialType::Principled(ref principled) => {
+            let frame = Onb::new(hit_record.normal, hit_record.tangent);
+            let wo = frame.to_local(-ray_incident.unit_vec());
+
+            let sample =
+                principled.sample(albedo(material, hit_record), wo, hit_record.front_face)?;
+
+            Some(Scatter {
+                scattered: Ray {
+                    origin: hit_record.p,
+                    direction: frame.to_world(sample.wi),
+                    ray_type: sample.ray_type,
                 },
-                attenuation: material.albedo,
+                attenuation: sample.weight,
             })
         }
-        MaterialType::Metal => Some(Scatter {
+        MaterialType::Layered { ref coat, ref base } => {
+            scatter_layered(coat, base, ray_incident, hit_record, scene)
+        }
+        MaterialType::Subsurface {
+            mean_free_path,
+            ior,
+        } => scatter_subsurface(
+            mean_free_path,
+            albedo(material, hit_record),
+            ior,
+            ray_incident,
+            hit_record,
+            scene,
+        ),
+        MaterialType::Volume { phase, .. } => Some(Scatter {
             scattered: Ray {
                 origin: hit_record.p,
-                // since ray_incident isn't normalized, we have to scale up the n using linear projection
-                direction: *ray_incident
-                    - 2. * ray_incident.dot(hit_record.normal) * hit_record.normal,
+                direction: phase.sample(ray_incident),
+                ray_type: RayType::Diffuse,
             },
-            attenuation: material.albedo,
+            attenuation: albedo(material, hit_record),
         }),
+        MaterialType::Mix {
+            ref a,
+            ref b,
+            ref factor,
+        } => {
+            // picking with the same probability that we'd weigh by means the
+            // weights cancel out, and we can just scatter off of whichever one
+            // we picked
+            let picked = if random_double(0.0..1.0) < mix_factor(factor.as_ref(), hit_record) {
+                b
+            } else {
+                a
+            };
+
+            // each side can have its own normal map, which replaces the mix's
+            // (the map is always applied to the geometric normal)
+            match &picked.normal_map {
+                Some(normal_map) => {
+                    let mut hit_record = hit_record.clone();
+                    perturb_normal(normal_map, &mut hit_record);
+                    scatter(picked, ray_incident, &hit_record, scene)
+                }
+                None => scatter(picked, ray_incident, hit_record, scene),
+            }
+        }
     }
 }
 
+/// What `sample` picked
+pub struct BsdfSample {
+    pub scattered: Ray,
+    /// `eval / pdf`, i.e. what to multiply the light coming back along
+    /// `scattered` by
+    pub weight: Color,
+    /// The pdf of picking `scattered`'s direction, per steradian. Only means
+    /// anything when it's `evaluable`.
+    pub pdf: f64,
+    /// Perfect mirrors and glass can only send light in one exact direction,
+    /// so there's no point in asking `eval` or `pdf` about any other one
+    pub delta: bool,
+    /// Whether or not `eval` and `pdf` agree with this sample. Never for
+    /// `delta` ones, and also not for the materials that do their own random
+    /// walk (`Layered` and `Subsurface`). Those do send light every which way,
+    /// we just can't work out how much goes where, so all we have is `weight`.
+    /// Once in a blue moon `pdf` also rounds to 0 for a direction that a
+    /// material did pick, in which case it isn't evaluable either.
+    pub evaluable: bool,
+}
+
+/// Fuzz below this counts as a perfect mirror. The fuzz sphere is so tiny
+/// that `fuzz_pdf` underflows to 0 for some of the directions that `scatter`
+/// picks, and we'd lose those bounces.
+const MIN_FUZZ: f64 = 1e-4;
+
+/// Only sends light in one exact direction, see `BsdfSample::delta`. A `Mix`
+/// depends on which side we pick.
+fn is_delta(material: &Material) -> bool {
+    match material.material_type {
+        MaterialType::Metal { fuzz } => fuzz < MIN_FUZZ,
+        MaterialType::Dielectric { .. } => true,
+        _ => false,
+    }
+}
+
+/// Whether or not `eval` and `pdf` know anything about this material, see
+/// `BsdfSample::evaluable`. Same as for `is_delta`, a `Mix` depends on which
+/// side we pick.
+fn is_evaluable(material: &Material) -> bool {
+    match material.material_type {
+        MaterialType::Layered { .. } | MaterialType::Subsurface { .. } => false,
+        _ => !is_delta(material),
+    }
+}
+
+/// The hit as one side of a `Mix` sees it. If the side has its own normal map
+/// it replaces the mix's, otherwise the side gets the mix's.
+fn mix_side_hit_record(side: &Material, hit_record: &HitRecord) -> HitRecord {
+    let mut hit_record = hit_record.clone();
+    if let Some(normal_map) = &side.normal_map {
+        perturb_normal(normal_map, &mut hit_record);
+    }
+    hit_record
+}
+
+/// The pdf of a fuzzy `Metal`'s direction, i.e. `reflected + fuzz * (a random
+/// point on the unit sphere)`. Those points are on a sphere of radius `fuzz`
+/// around the tip of `reflected`, so every direction that goes through that
+/// sphere hits it twice, and the pdf is the (uniform) density on the sphere
+/// turned into solid angle at both of those points.
+fn fuzz_pdf(reflected: &Vec3, fuzz: f64, wi: &Vec3) -> f64 {
+    let fuzz = fuzz.clamp(0., 1.);
+    let b = wi.unit_vec().dot(*reflected);
+    let discriminant = b * b - 1. + fuzz * fuzz;
+    if fuzz <= 0. || discriminant <= 0. {
+        return 0.;
+    }
+    let root = discriminant.sqrt();
+
+    [b - root, b + root]
+        .into_iter()
+        .filter(|&s| s > 0.)
+        .map(|s| s * s / (4. * PI * fuzz * root))
+        .sum()
+}
+
+/// The pdf of `Diffuse`'s direction, i.e. `(a random point on the unit
+/// hemisphere) + (a random point on the unit sphere)`, in local space. For a
+/// fixed point `h` on the hemisphere, adding the sphere gives a cosine lobe
+/// around `h` (see `fuzz_pdf` with a fuzz of 1), and averaging those lobes
+/// over the hemisphere comes out to `(1 + cos) / 4pi`. It isn't lambert, it
+/// even sends a little bit of light below the surface, but it's what this
+/// tracer has always done.
+fn diffuse_pdf(wi_local: Vec3) -> f64 {
+    (1. + wi_local.z) / (4. * PI)
+}
+
+/// The BSDF for light coming in from `wi` and going back out against
+/// `ray_incident`, times the cosine at `wi`. Both are in world space, `wi`
+/// points away from the surface.
+///
+/// This is what light sampling needs, where we pick the direction (towards a
+/// light) instead of the material. Always black for materials that aren't
+/// evaluable, see `BsdfSample`.
+pub fn eval(material: &Material, ray_incident: &Vec3, wi: &Vec3, hit_record: &HitRecord) -> Color {
+    let frame = Onb::new(hit_record.normal, hit_record.tangent);
+    let wo_local = frame.to_local(-ray_incident.unit_vec());
+    let wi_local = frame.to_local(wi.unit_vec());
+
+    match material.material_type {
+        // the weight is always the albedo, same as a fuzzy metal
+        MaterialType::Diffuse => diffuse_pdf(wi_local) * albedo(material, hit_record),
+        MaterialType::OrenNayar { sigma } if wi_local.z > 0. => {
+            oren_nayar(sigma, wo_local, wi_local) * wi_local.z / PI * albedo(material, hit_record)
+        }
+        MaterialType::Translucent { ref transmittance } => {
+            let Some((reflectance, transmittance, _)) =
+                translucent_split(material, transmittance.as_ref(), hit_record)
+            else {
+                return Color::default();
+            };
+            let side = if wi_local.z > 0. {
+                reflectance
+            } else {
+                transmittance
+            };
+            wi_local.z.abs() / PI * side
+        }
+        MaterialType::Metal { fuzz } if fuzz >= MIN_FUZZ && wi.dot(hit_record.normal) > 0. => {
+            // the weight is always the albedo, so the BSDF is just the
+            // albedo wherever it could have sent the light
+            let reflected = reflect(ray_incident, &hit_record.normal).unit_vec();
+            fuzz_pdf(&reflected, fuzz, wi) * albedo(material, hit_record)
+        }
+        MaterialType::Conductor { .. } | MaterialType::AnisotropicConductor { .. } => {
+            let Some((frame, ggx, eta, k)) = conductor_lobe(material, hit_record) else {
+                return Color::default();
+            };
+            let wo_local = frame.to_local(-ray_incident.unit_vec());
+            let wi_local = frame.to_local(wi.unit_vec());
+            eval_microfacet_reflection(
+                &ggx,
+                wo_local,
+                wi_local,
+                conductor_fresnel(material, eta, k),
+            ) * albedo(material, hit_record)
+        }
+        MaterialType::RoughDielectric { ior, roughness, .. } => {
+            let eta = if hit_record.front_face { ior } else { 1. / ior };
+            eval_rough_dielectric(
+                &Ggx::isotropic(roughness),
+                wo_local,
+                wi_local,
+                eta,
+                dielectric_fresnel(material, ior, hit_record),
+            ) * albedo(material, hit_record)
+        }
+        MaterialType::Principled(ref principled) => principled.eval(
+            albedo(material, hit_record),
+            wo_local,
+            wi_local,
+            hit_record.front_face,
+        ),
+        MaterialType::Volume { phase, .. } => {
+            phase.pdf(ray_incident, wi) * albedo(material, hit_record)
+        }
+        MaterialType::Mix {
+            ref a,
+            ref b,
+            ref factor,
+        } => {
+            let f = mix_factor(factor.as_ref(), hit_record);
+            let side = |side: &Material| {
+                eval(
+                    side,
+                    ray_incident,
+                    wi,
+                    &mix_side_hit_record(side, hit_record),
+                )
+            };
+            (1. - f) * side(a) + f * side(b)
+        }
+        _ => Color::default(),
+    }
+}
+
+/// The pdf of `sample` picking `wi`, per steradian. Same arguments as `eval`.
+pub fn pdf(material: &Material, ray_incident: &Vec3, wi: &Vec3, hit_record: &HitRecord) -> f64 {
+    let frame = Onb::new(hit_record.normal, hit_record.tangent);
+    let wo_local = frame.to_local(-ray_incident.unit_vec());
+    let wi_local = frame.to_local(wi.unit_vec());
+
+    match material.material_type {
+        MaterialType::Diffuse => diffuse_pdf(wi_local),
+        MaterialType::OrenNayar { .. } if wi_local.z > 0. => wi_local.z / PI,
+        MaterialType::Translucent { ref transmittance } => {
+            let Some((_, _, reflect_probability)) =
+                translucent_split(material, transmittance.as_ref(), hit_record)
+            else {
+                return 0.;
+            };
+            let side = if wi_local.z > 0. {
+                reflect_probability
+            } else {
+                1. - reflect_probability
+            };
+            side * wi_local.z.abs() / PI
+        }
+        MaterialType::Metal { fuzz } if fuzz >= MIN_FUZZ && wi.dot(hit_record.normal) > 0. => {
+            let reflected = reflect(ray_incident, &hit_record.normal).unit_vec();
+            fuzz_pdf(&reflected, fuzz, wi)
+        }
+        MaterialType::Conductor { .. } | MaterialType::AnisotropicConductor { .. } => {
+            let Some((frame, ggx, ..)) = conductor_lobe(material, hit_record) else {
+                return 0.;
+            };
+            let wo_local = frame.to_local(-ray_incident.unit_vec());
+            let wi_local = frame.to_local(wi.unit_vec());
+            pdf_microfacet_reflection(&ggx, wo_local, wi_local)
+        }
+        MaterialType::RoughDielectric { ior, roughness, .. } => {
+            let eta = if hit_record.front_face { ior } else { 1. / ior };
+            pdf_rough_dielectric(
+                &Ggx::isotropic(roughness),
+                wo_local,
+                wi_local,
+                eta,
+                dielectric_fresnel(material, ior, hit_record),
+            )
+        }
+        MaterialType::Principled(ref principled) => {
+            principled.pdf(wo_local, wi_local, hit_record.front_face)
+        }
+        MaterialType::Volume { phase, .. } => phase.pdf(ray_incident, wi),
+        MaterialType::Mix {
+            ref a,
+            ref b,
+            ref factor,
+        } => {
+            let f = mix_factor(factor.as_ref(), hit_record);
+            let side = |side: &Material| {
+                pdf(
+                    side,
+                    ray_incident,
+                    wi,
+                    &mix_side_hit_record(side, hit_record),
+                )
+            };
+            (1. - f) * side(a) + f * side(b)
+        }
+        _ => 0.,
+    }
+}
+
+/// Picks a direction for the light to bounce off in, along with its pdf.
+/// Directions come from `scatter`, but the weight is always `eval / pdf`, so
+/// that all three always agree with each other.
+pub fn sample(
+    material: &Material,
+    ray_incident: &Vec3,
+    hit_record: &HitRecord,
+    scene: &mut Scene,
+) -> Option<BsdfSample> {
+    if let MaterialType::Mix {
+        ref a,
+        ref b,
+        ref factor,
+    } = material.material_type
+    {
+        let side = if random_double(0.0..1.0) < mix_factor(factor.as_ref(), hit_record) {
+            b
+        } else {
+            a
+        };
+        let side_sample = sample(
+            side,
+            ray_incident,
+            &mix_side_hit_record(side, hit_record),
+            scene,
+        )?;
+        // either the other side can't possibly send light in exactly this
+        // direction, or we can't tell how likely this side was to pick it. In
+        // both cases this is the same as `scatter`'s mix.
+        if !side_sample.evaluable {
+            return Some(side_sample);
+        }
+
+        // otherwise both sides could have picked this direction, so we weigh
+        // by both of them (one sample MIS), which is a lot less noisy when one
+        // side is way shinier than the other
+        let direction = side_sample.scattered.direction;
+        let pdf = pdf(material, ray_incident, &direction, hit_record);
+        // same as below, this can only be rounding
+        if pdf <= 0. {
+            return Some(side_sample);
+        }
+        return Some(BsdfSample {
+            weight: eval(material, ray_incident, &direction, hit_record) / pdf,
+            pdf,
+            ..side_sample
+        });
+    }
+
+    let scatter = scatter(material, ray_incident, hit_record, scene)?;
+    let direction = scatter.scattered.direction;
+    let pdf = if is_evaluable(material) {
+        pdf(material, ray_incident, &direction, hit_record)
+    } else {
+        0.
+    };
+    // `scatter` did pick this direction, so an evaluable material can only get
+    // a pdf of 0 from rounding (e.g. right on the edge of a tiny fuzz sphere).
+    // Keep `scatter`'s weight instead of losing the bounce.
+    if pdf <= 0. {
+        return Some(BsdfSample {
+            scattered: scatter.scattered,
+            weight: scatter.attenuation,
+            pdf: 0.,
+            delta: is_delta(material),
+            evaluable: false,
+        });
+    }
+    Some(BsdfSample {
+        weight: eval(material, ray_incident, &direction, hit_record) / pdf,
+        scattered: scatter.scattered,
+        pdf,
+        delta: false,
+        evaluable: true,
+    })
+}
+
+/// How much brighter (or darker) oren nayar is than lambert for light going
+/// from `wi` to `wo`, in local space. This is the qualitative model (the one
+/// pbrt uses).
+pub fn oren_nayar(sigma: f64, wo: Vec3, wi: Vec3) -> f64 {
+    let sigma2 = sigma * sigma;
+    let a = 1. - sigma2 / (2. * (sigma2 + 0.33));
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+    let sin_theta_i = (1. - wi.z * wi.z).max(0.).sqrt();
+    let sin_theta_o = (1. - wo.z * wo.z).max(0.).sqrt();
+
+    // cos of the angle between wi and wo when looking straight down the normal
+    let max_cos = if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+        ((wi.x * wo.x + wi.y * wo.y) / (sin_theta_i * sin_theta_o)).max(0.)
+    } else {
+        0.
+    };
+
+    // sin(alpha) * tan(beta), where alpha is the bigger of the two angles from
+    // the normal and beta is the smaller one
+    let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs() {
+        (sin_theta_o, sin_theta_i / wi.z.abs())
+    } else {
+        (sin_theta_i, sin_theta_o / wo.z.abs().max(1e-4))
+    };
+
+    a + b * max_cos * sin_alpha * tan_beta
+}
+
+/// Reflection off of a GGX metal, for both `Conductor` and
+/// `AnisotropicConductor`. `frame` decides which way the roughness is
+/// stretched.
+fn scatter_conductor(
+    material: &Material,
+    eta: Color,
+    k: Color,
+    ggx: &Ggx,
+    frame: &Onb,
+    ray_incident: &Vec3,
+    hit_record: &HitRecord,
+) -> Option<Scatter> {
+    // everything from here on is in local space, pointing away from the
+    // surface
+    let wo = frame.to_local(-ray_incident.unit_vec());
+    if wo.z <= 0. {
+        return None;
+    }
+
+    let m = ggx.sample_visible_normal(wo, random_double(0.0..1.0), random_double(0.0..1.0));
+    let wi = reflect_local(wo, m);
+    // bounced off a facet but still went into the surface
+    if wi.z <= 0. {
+        return None;
+    }
+
+    // with visible normal sampling, the D and most of the G cancel out with the
+    // pdf, so we're just left with F * G2 / G1
+    let fresnel = conductor_fresnel(material, eta, k)(wo.dot(m));
+    let weight = ggx.g2(wo, wi) / ggx.g1(wo) * fresnel;
+
+    Some(Scatter {
+        scattered: Ray {
+            origin: hit_record.p,
+            direction: frame.to_world(wi),
+            ray_type: RayType::Specular,
+        },
+        attenuation: weight * albedo(material, hit_record),
+    })
+}
+
+/// Which way the brushing goes at a hit. Starts from the surface's tangent,
+/// optionally swaps it out for the direction in the tangent map, and then spins
+/// it around the normal by `rotation`.
+fn anisotropic_tangent(
+    rotation: f64,
+    tangent_map: Option<&dyn Texture>,
+    hit_record: &HitRecord,
+) -> Vec3 {
+    let surface = Onb::new(hit_record.normal, hit_record.tangent);
+
+    let local = match tangent_map {
+        Some(tangent_map) => {
+            let c = tangent_map.value(hit_record.u, hit_record.v, &hit_record.p);
+            vec3![2. * c.x - 1., 2. * c.y - 1., 0.]
+        }
+        None => vec3![1., 0., 0.],
+    };
+    let (sin, cos) = rotation.sin_cos();
+    let rotated = vec3![
+        local.x * cos - local.y * sin,
+        local.x * sin + local.y * cos,
+        0.
+    ];
+
+    if rotated.len_squared() < 1e-12 {
+        return hit_record.tangent;
+    }
+    surface.to_world(rotated)
+}
+
+/// How much light a material gives off by itself at a hit, which gets added on
+/// top of whatever light is scattered
+pub fn emitted(material: &Material, hit_record: &HitRecord) -> Color {
+    match material.material_type {
+        MaterialType::Emissive { strength } => strength * albedo(material, hit_record),
+        MaterialType::Principled(ref principled) => principled.emission,
+        MaterialType::Volume {
+            emission: Some(ref emission),
+            ..
+        } => {
+            let absorbed = vec3![1., 1., 1.] - albedo(material, hit_record);
+            absorbed * emission.value(hit_record.u, hit_record.v, &hit_record.p)
+        }
+        // light from the base gets tinted by the coat, but not the fresnel
+        // that bounces some of it back down, that's close enough
+        MaterialType::Layered { ref coat, ref base } => coat.absorption * emitted(base, hit_record),
+        MaterialType::Mix {
+            ref a,
+            ref b,
+            ref factor,
+        } => lerp(
+            emitted(a, hit_record),
+            emitted(b, hit_record),
+            mix_factor(factor.as_ref(), hit_record),
+        ),
+        _ => Color::default(),
+    }
+}
+
+/// Mirror `v` around the surface with normal `n`
+pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
+    // since v isn't normalized, we have to scale up the n using linear projection
+    *v - 2. * v.dot(*n) * *n
+}
+
+/// Bend the unit vector `uv` through a surface with normal `n`, using snell's
+/// law. `etai_over_etat` is the ratio of the refractive indices of the side
+/// we're coming from over the side we're going into.
+///
+/// Split the refracted ray into the part perpendicular to the normal and the
+/// part parallel to it, the perpendicular part is just the ratio times the
+/// incident perpendicular part, and the parallel part is whatever is left over
+/// to make it a unit vector.
+pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
+    let cos_theta = (-*uv).dot(*n).min(1.);
+    let r_out_perp = etai_over_etat * (*uv + cos_theta * *n);
+    let r_out_parallel = -(1. - r_out_perp.len_squared()).abs().sqrt() * *n;
+    r_out_perp + r_out_parallel
+}
+
+/// Schlick's approximation for how much light gets reflected instead of
+/// refracted, depending on the angle
+pub fn reflectance(cosine: f64, refraction_ratio: f64) -> f64 {
+    let r0 = ((1. - refraction_ratio) / (1. + refraction_ratio)).powi(2);
+    r0 + (1. - r0) * (1. - cosine).powi(5)
+}
+
 // pub trait Material where Self: Clone {
 //     fn albedo(&self) -> f64;
 //     fn scatter(&self, ray_incident: Vec3, hit_record: &HitRecord) -> Vec3;
@@ -108,3 +1090,156 @@ pub fn scatter(
 //         todo!()
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::Interior;
+    use crate::microfacet::{sphere_directions, SAMPLES};
+    use crate::principled::Principled;
+
+    fn material(material_type: MaterialType) -> Material {
+        Material {
+            albedo: solid(vec3![0.8, 0.8, 0.8]),
+            material_type,
+            normal_map: None,
+            thin_film: None,
+        }
+    }
+
+    /// Light coming in at an angle onto a surface facing +z
+    fn hit() -> (Vec3, HitRecord) {
+        let ray_incident = vec3![0.3, -0.2, -1.].unit_vec();
+        let ray = Ray {
+            origin: vec3![0., 0., 1.],
+            direction: ray_incident,
+            ray_type: RayType::Camera,
+        };
+        let hit_record = HitRecord::new(vec3![0., 0., 0.], 1., vec3![0., 0., 1.], &ray, None);
+        (ray_incident, hit_record)
+    }
+
+    /// Monte carlo checks that `sample`, `eval` and `pdf` agree with each
+    /// other, for the light from `hit`:
+    /// - every sample's weight is `eval / pdf`, and its pdf is `pdf`
+    /// - the average weight is the integral of `eval` over the sphere
+    /// - `pdf` integrates to the chance that `sample` returns anything, i.e.
+    ///   1 unless some of the light gets absorbed (e.g. bounced into the
+    ///   surface)
+    fn check(material: &Material) {
+        let (ray_incident, hit_record) = hit();
+        let mut scene = Scene::default();
+
+        let mut total_weight = 0.;
+        let mut successes = 0;
+        for _ in 0..SAMPLES {
+            let Some(sample) = sample(material, &ray_incident, &hit_record, &mut scene) else {
+                continue;
+            };
+            assert!(sample.evaluable && !sample.delta);
+            let direction = sample.scattered.direction;
+            let pdf = pdf(material, &ray_incident, &direction, &hit_record);
+            let eval = eval(material, &ray_incident, &direction, &hit_record);
+            assert!((sample.pdf - pdf).abs() <= 1e-6 * pdf);
+            assert!((sample.weight - eval / pdf).len() <= 1e-6 * sample.weight.len());
+
+            total_weight += sample.weight.x;
+            successes += 1;
+        }
+
+        let directions = sphere_directions();
+        let mut total_eval = 0.;
+        let mut total_pdf = 0.;
+        for wi in &directions {
+            total_eval += eval(material, &ray_incident, wi, &hit_record).x;
+            total_pdf += pdf(material, &ray_incident, wi, &hit_record);
+        }
+        // the sphere has an area of 4pi
+        let integrated_eval = 4. * PI * total_eval / directions.len() as f64;
+        let integrated_pdf = 4. * PI * total_pdf / directions.len() as f64;
+
+        let mean_weight = total_weight / SAMPLES as f64;
+        let success_rate = successes as f64 / SAMPLES as f64;
+        assert!(
+            (mean_weight - integrated_eval).abs() < 0.03,
+            "average weight {mean_weight}, but eval integrates to {integrated_eval}"
+        );
+        assert!(
+            (success_rate - integrated_pdf).abs() < 0.03,
+            "{success_rate} of the samples made it, but pdf integrates to {integrated_pdf}"
+        );
+        assert!(integrated_pdf < 1.03, "pdf integrates to {integrated_pdf}");
+    }
+
+    #[test]
+    fn diffuse() {
+        check(&material(MaterialType::Diffuse));
+    }
+
+    #[test]
+    fn fuzzy_metal() {
+        check(&material(MaterialType::Metal { fuzz: 0.5 }));
+    }
+
+    #[test]
+    fn tiny_fuzz_never_loses_bounces() {
+        let (ray_incident, hit_record) = hit();
+        let mut scene = Scene::default();
+        for fuzz in [1e-9, 1e-6, 1e-5, 3e-4, 1e-3] {
+            let metal = material(MaterialType::Metal { fuzz });
+            for _ in 0..10_000 {
+                let sample = sample(&metal, &ray_incident, &hit_record, &mut scene);
+                assert!(sample.is_some(), "lost a bounce with a fuzz of {fuzz}");
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric() {
+        check(&material(MaterialType::RoughDielectric {
+            ior: 1.5,
+            roughness: 0.5,
+            interior: Interior::default(),
+        }));
+    }
+
+    #[test]
+    fn principled() {
+        check(&material(MaterialType::Principled(Principled {
+            metallic: 0.3,
+            sheen: 0.5,
+            clearcoat: 1.,
+            clearcoat_gloss: 0.5,
+            ..Principled::default()
+        })));
+    }
+
+    #[test]
+    fn mix() {
+        check(&material(MaterialType::mix(
+            material(MaterialType::Diffuse),
+            material(MaterialType::Metal { fuzz: 0.3 }),
+            0.4,
+        )));
+    }
+
+    #[test]
+    fn random_walks_are_not_evaluable() {
+        let layered = material(MaterialType::Layered {
+            coat: Coat {
+                ior: 1.5,
+                roughness: 0.,
+                absorption: vec3![1., 1., 1.],
+            },
+            base: Box::new(material(MaterialType::Diffuse)),
+        });
+        let subsurface = material(MaterialType::Subsurface {
+            mean_free_path: vec3![1., 1., 1.],
+            ior: 1.3,
+        });
+        for material in [layered, subsurface] {
+            assert!(!is_delta(&material) && !is_evaluable(&material));
+        }
+        assert!(is_delta(&material(MaterialType::metal())));
+    }
+}
@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{Material, MaterialType};
+use crate::vec3;
+
+/// What the inside of a `Dielectric` or `RoughDielectric` is like, on top of
+/// its ior
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Interior {
+    /// How quickly each color gets soaked up going through the inside, per
+    /// unit of distance (beer-lambert). This is where colored glass and
+    /// liquids get their color from, thick parts are darker than thin ones.
+    /// Black is perfectly clear.
+    pub absorption: Color,
+    /// Decides who wins when two objects overlap, like a liquid that goes a
+    /// little bit into the walls of its glass (which is how you should model
+    /// it, so there's no gap of air between them). Inside of an object, the
+    /// surfaces of anything with a lower priority are ignored, so whichever
+    /// object should fill the space where they overlap gets the higher
+    /// priority. That's the glass over the liquid poking into its walls, but
+    /// also the ice over the drink that it's floating in.
+    pub priority: u32,
+}
+
+/// A dielectric object that a path is inside of
+#[derive(Debug, Clone)]
+struct Medium {
+    /// Which object it is. Objects that share a material count as the same
+    /// medium, which is fine since they'd be touching the same stuff anyways.
+    material: Rc<RefCell<Material>>,
+    ior: f64,
+    interior: Interior,
+}
+
+/// Everything that a path is currently inside of, so that light going from
+/// one dielectric straight into another (ice in a drink) bends by the ratio of
+/// *their* iors instead of assuming there's air around everything. Every
+/// bounce in `ray_color` gets its own copy.
+///
+/// Schmidt and Budge, "Simple Nested Dielectrics in Ray Traced Images" (2002)
+#[derive(Debug, Default, Clone)]
+pub struct MediumStack {
+    media: Vec<Medium>,
+}
+
+/// What `MediumStack::cross` decided to do with a hit
+pub enum Crossing {
+    /// A surface of a lower priority object, inside of a higher priority one.
+    /// Keep going straight through it, with this stack.
+    Ignored(MediumStack),
+    /// A real surface. Scatter off of `material`, which has its ior swapped
+    /// for the one relative to whatever is on the other side. If the ray goes
+    /// through it, it ends up in `transmitted`.
+    Boundary {
+        material: Material,
+        transmitted: MediumStack,
+    },
+}
+
+/// The ior and interior of the materials that have an inside
+fn dielectric_interior(material: &Material) -> Option<(f64, Interior)> {
+    match material.material_type {
+        MaterialType::Dielectric { ior, interior }
+        | MaterialType::RoughDielectric { ior, interior, .. } => Some((ior, interior)),
+        _ => None,
+    }
+}
+
+impl MediumStack {
+    /// The medium that wins, i.e. the one with the highest priority. The last
+    /// one we went into, if there's a tie.
+    fn current(&self) -> Option<&Medium> {
+        self.media
+            .iter()
+            .max_by_key(|medium| medium.interior.priority)
+    }
+
+    fn without(&self, material: &Rc<RefCell<Material>>) -> MediumStack {
+        let mut media = self.media.clone();
+        if let Some(i) = media
+            .iter()
+            .rposition(|medium| Rc::ptr_eq(&medium.material, material))
+        {
+            media.remove(i);
+        }
+        MediumStack { media }
+    }
+
+    /// Out in the open, not inside of anything
+    pub fn is_empty(&self) -> bool {
+        self.media.is_empty()
+    }
+
+    fn ior(&self) -> f64 {
+        self.current().map_or(1., |medium| medium.ior)
+    }
+
+    /// How much of each color survives going `distance` through whatever we're
+    /// in right now
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let Some(medium) = self.current() else {
+            return vec3![1., 1., 1.];
+        };
+        let absorption = medium.interior.absorption;
+        // 0 * infinity would be nan, and clear stuff should stay clear even
+        // for rays that go on forever
+        let channel = |a: f64| {
+            if a > 0. {
+                (-a * distance).exp()
+            } else {
+                1.
+            }
+        };
+        vec3![
+            channel(absorption.x),
+            channel(absorption.y),
+            channel(absorption.z)
+        ]
+    }
+
+    /// Whether or not the hit should count as one (see `Crossing`), and
+    /// what's on the other side. `None` if the material isn't a dielectric, in
+    /// which case nothing changes.
+    pub fn cross(&self, hit_record: &HitRecord) -> Option<Crossing> {
+        let object = hit_record.material.as_ref()?;
+        let material = object.borrow();
+        let (ior, interior) = dielectric_interior(&material)?;
+
+        let (inside, outside) = if hit_record.front_face {
+            let mut inside = self.clone();
+            inside.media.push(Medium {
+                material: object.clone(),
+                ior,
+                interior,
+            });
+            (inside, self.clone())
+        } else {
+            (self.clone(), self.without(object))
+        };
+
+        // whatever wins inside of the object is something else, so this
+        // surface is just where the object overlaps with it
+        let winner = inside.current()?;
+        if !Rc::ptr_eq(&winner.material, object) && winner.interior.priority > interior.priority {
+            let next = if hit_record.front_face {
+                inside
+            } else {
+                outside
+            };
+            return Some(Crossing::Ignored(next));
+        }
+
+        // scatter only knows about one ior, so give it the one relative to
+        // the other side
+        let mut relative = material.clone();
+        let relative_ior = ior / outside.ior();
+        match relative.material_type {
+            MaterialType::Dielectric { ref mut ior, .. }
+            | MaterialType::RoughDielectric { ref mut ior, .. } => *ior = relative_ior,
+            _ => unreachable!(),
+        }
+
+        Some(Crossing::Boundary {
+            material: relative,
+            transmitted: if hit_record.front_face {
+                inside
+            } else {
+                outside
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::texture::solid;
+    use crate::vec3;
+
+    fn dielectric(ior: f64, priority: u32) -> Rc<RefCell<Material>> {
+        Rc::new(RefCell::new(Material {
+            albedo: solid(vec3![1., 1., 1.]),
+            material_type: MaterialType::Dielectric {
+                ior,
+                interior: Interior {
+                    absorption: vec3![0., 0., 0.],
+                    priority,
+                },
+            },
+            normal_map: None,
+            thin_film: None,
+        }))
+    }
+
+    /// A ray going into (`front_face`) or out of `object`
+    fn hit(object: &Rc<RefCell<Material>>, front_face: bool) -> HitRecord {
+        let ray = Ray {
+            origin: vec3![0., 0., 1.],
+            direction: vec3![0., 0., -1.],
+            ray_type: Default::default(),
+        };
+        let outward_normal = if front_face {
+            vec3![0., 0., 1.]
+        } else {
+            vec3![0., 0., -1.]
+        };
+        HitRecord::new(
+            vec3![0., 0., 0.],
+            1.,
+            outward_normal,
+            &ray,
+            Some(object.clone()),
+        )
+    }
+
+    /// The ior that `cross` handed to scatter, and the stack on the other side
+    fn boundary(crossing: Option<Crossing>) -> (f64, MediumStack) {
+        match crossing {
+            Some(Crossing::Boundary {
+                material,
+                transmitted,
+            }) => match material.material_type {
+                MaterialType::Dielectric { ior, .. } => (ior, transmitted),
+                _ => panic!("not a dielectric anymore"),
+            },
+            _ => panic!("expected a boundary"),
+        }
+    }
+
+    fn ignored(crossing: Option<Crossing>) -> MediumStack {
+        match crossing {
+            Some(Crossing::Ignored(next)) => next,
+            _ => panic!("expected the surface to be ignored"),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{a} != {b}");
+    }
+
+    #[test]
+    fn glass_of_liquid_with_ice() {
+        let glass = dielectric(1.5, 2);
+        let liquid = dielectric(1.33, 1);
+        let ice = dielectric(1.31, 2);
+
+        // into the glass from the air
+        let (ior, in_glass) = boundary(MediumStack::default().cross(&hit(&glass, true)));
+        assert_close(ior, 1.5);
+        assert_close(in_glass.ior(), 1.5);
+
+        // the liquid pokes into the walls, so its surface in there is ignored
+        let in_both = ignored(in_glass.cross(&hit(&liquid, true)));
+        assert_close(in_both.ior(), 1.5);
+
+        // out of the inner wall of the glass, straight into the liquid
+        let (ior, in_liquid) = boundary(in_both.cross(&hit(&glass, false)));
+        assert_close(ior, 1.5 / 1.33);
+        assert_close(in_liquid.ior(), 1.33);
+
+        // into the ice, and back out into the liquid
+        let (ior, in_ice) = boundary(in_liquid.cross(&hit(&ice, true)));
+        assert_close(ior, 1.31 / 1.33);
+        assert_close(in_ice.ior(), 1.31);
+        let (ior, back_in_liquid) = boundary(in_ice.cross(&hit(&ice, false)));
+        assert_close(ior, 1.31 / 1.33);
+        assert_close(back_in_liquid.ior(), 1.33);
+
+        // the liquid's surface inside of the glass wall on the way out is
+        // ignored too, and then out of the glass into the air
+        let (ior, in_glass) = boundary(in_liquid.cross(&hit(&glass, true)));
+        assert_close(ior, 1.5 / 1.33);
+        let in_glass = ignored(in_glass.cross(&hit(&liquid, false)));
+        let (ior, outside) = boundary(in_glass.cross(&hit(&glass, false)));
+        assert_close(ior, 1.5);
+        assert!(outside.is_empty());
+    }
+
+    #[test]
+    fn lower_priority_ice_disappears() {
+        let liquid = dielectric(1.33, 1);
+        let ice = dielectric(1.31, 0);
+
+        let (_, in_liquid) = boundary(MediumStack::default().cross(&hit(&liquid, true)));
+        let in_both = ignored(in_liquid.cross(&hit(&ice, true)));
+        assert_close(in_both.ior(), 1.33);
+    }
+
+    #[test]
+    fn other_materials_dont_change_anything() {
+        let diffuse = Rc::new(RefCell::new(Material {
+            albedo: solid(vec3![1., 1., 1.]),
+            material_type: MaterialType::Diffuse,
+            normal_map: None,
+            thin_film: None,
+        }));
+        assert!(MediumStack::default().cross(&hit(&diffuse, true)).is_none());
+    }
+}
@@ -0,0 +1,462 @@
+use std::f64::consts::PI;
+
+use crate::color::Color;
+use crate::random_double;
+use crate::vec::*;
+use crate::vec3;
+
+/// The smallest alpha we let through. At exactly 0 the distribution turns into
+/// a spike with infinite height, so we just get really close instead.
+const MIN_ALPHA: f64 = 1e-4;
+
+/// GGX (a.k.a. Trowbridge-Reitz) microfacet distribution.
+///
+/// The idea is that a rough surface is really a bunch of tiny perfect mirrors
+/// (microfacets) pointing in slightly different directions, and this describes
+/// how many of them point in each direction. Everything here is in *local*
+/// space, where the surface normal is +z (see `Onb`).
+///
+/// `alpha_x` and `alpha_y` are how rough the surface is along the tangent and
+/// bitangent. They're the same for normal (isotropic) surfaces.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    /// Artists think in "roughness", which looks more linear to the eye when
+    /// squared into alpha
+    pub fn isotropic(roughness: f64) -> Self {
+        Self::anisotropic(roughness, roughness)
+    }
+
+    pub fn anisotropic(roughness_x: f64, roughness_y: f64) -> Self {
+        Self {
+            alpha_x: (roughness_x * roughness_x).max(MIN_ALPHA),
+            alpha_y: (roughness_y * roughness_y).max(MIN_ALPHA),
+        }
+    }
+
+    /// How many microfacets point in direction `m`
+    pub fn d(&self, m: Vec3) -> f64 {
+        if m.z <= 0. {
+            return 0.;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1. / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    /// Smith's lambda, basically how much of the surface is hidden behind other
+    /// microfacets when looking from `w`
+    pub fn lambda(&self, w: Vec3) -> f64 {
+        if w.z == 0. {
+            return f64::MAX;
+        }
+        let a2_tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        (-1. + (1. + a2_tan2).sqrt()) / 2.
+    }
+
+    /// Fraction of the microfacets that are visible from `w`
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets that are visible from both `wo` and `wi`
+    /// (height correlated, so a facet that's hidden from one is more likely to
+    /// be hidden from the other too)
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Picks a microfacet normal, but only out of the ones that `wo` can
+    /// actually see. Way less noisy than sampling `d` directly, since we never
+    /// waste samples on facets that face away from us.
+    ///
+    /// Eric Heitz, "Sampling the GGX Distribution of Visible Normals" (2018)
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // stretch the view direction so that the distribution becomes a
+        // hemisphere with alpha = 1
+        let vh = vec3![self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z].unit_vec();
+
+        // orthonormal basis around the view direction
+        let len_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_squared > 0. {
+            vec3![-vh.y, vh.x, 0.] / len_squared.sqrt()
+        } else {
+            vec3![1., 0., 0.]
+        };
+        let t2 = vh.cross(t1);
+
+        // uniformly pick a point on the disk, then squish half of it down to
+        // account for the part of the hemisphere that's facing away from us
+        let r = u1.sqrt();
+        let phi = 2. * PI * u2;
+        let p1 = r * phi.cos();
+        let p2 = r * phi.sin();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * p2;
+
+        // lift the point up onto the hemisphere
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+        // and unstretch
+        vec3![self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(0.)].unit_vec()
+    }
+
+    /// The pdf of `sample_visible_normal` picking `m`
+    pub fn pdf_visible_normal(&self, wo: Vec3, m: Vec3) -> f64 {
+        if wo.z <= 0. {
+            return 0.;
+        }
+        self.g1(wo) * wo.dot(m).max(0.) * self.d(m) / wo.z
+    }
+}
+
+/// Mirror `w` around `m`, where `w` points *away* from the surface
+pub fn reflect_local(w: Vec3, m: Vec3) -> Vec3 {
+    2. * w.dot(m) * m - w
+}
+
+/// Bend `w` (pointing *away* from the surface) through a microfacet with
+/// normal `m`, into the other side. `eta` is the index of refraction of the
+/// side we're going into over the side we're coming from.
+///
+/// `None` means total internal reflection.
+pub fn refract_local(w: Vec3, m: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = w.dot(m);
+    let sin2_theta_t = (1. - cos_theta_i * cos_theta_i).max(0.) / (eta * eta);
+    if sin2_theta_t >= 1. {
+        return None;
+    }
+    let cos_theta_t = (1. - sin2_theta_t).sqrt();
+    Some(-w / eta + (cos_theta_i / eta - cos_theta_t) * m)
+}
+
+/// How much light gets reflected (instead of refracted) at the boundary of a
+/// dielectric, from the full (unpolarized) fresnel equations instead of
+/// schlick. `eta` is the same as in `refract_local`. Gives 1 for total
+/// internal reflection.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0., 1.);
+    let sin2_theta_t = (1. - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1. {
+        return 1.;
+    }
+    let cos_theta_t = (1. - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Picks which way light goes through a rough dielectric (glass) surface, i.e.
+/// reflecting or refracting off of one of its visible facets. `eta` is the
+/// same as in `refract_local`.
+///
+/// We pick reflection or refraction with the fresnel of the facet we picked.
+/// Since we choose with exactly the probability that the fresnel term would
+/// weigh it by, it cancels out, and so do the jacobians that turn a pdf over
+/// facet normals into a pdf over directions (they show up on both the top and
+/// the bottom). All that's left over for the weight is the `G2 / G1` from
+/// visible normal sampling, which goes to 1 as roughness goes to 0, i.e. no
+/// energy lost.
+pub fn sample_rough_dielectric(ggx: &Ggx, wo: Vec3, eta: f64) -> Option<Vec3> {
+    let fresnel = |cos_theta_i| {
+        let f = fresnel_dielectric(cos_theta_i, eta);
+        vec3![f, f, f]
+    };
+    sample_rough_dielectric_with(ggx, wo, eta, fresnel).map(|(wi, _)| wi)
+}
+
+/// Same as `sample_rough_dielectric`, but for a fresnel that's different for
+/// every channel (e.g. with a thin film on top). We can only pick one way to
+/// go, so we pick with the average, and the weight that makes up for it gets
+/// returned along with the direction (on top of the `G2 / G1`).
+pub fn sample_rough_dielectric_with(
+    ggx: &Ggx,
+    wo: Vec3,
+    eta: f64,
+    fresnel: impl Fn(f64) -> Color,
+) -> Option<(Vec3, Color)> {
+    let m = ggx.sample_visible_normal(wo, random_double(0.0..1.0), random_double(0.0..1.0));
+    let f = fresnel(wo.dot(m));
+    let reflect_probability = (f.x + f.y + f.z) / 3.;
+
+    if reflect_probability > random_double(0.0..1.0) {
+        let wi = reflect_local(wo, m);
+        (wi.z > 0.).then_some((wi, f / reflect_probability))
+    } else {
+        // fresnel is 1 for total internal reflection, so this can't actually
+        // fail
+        let wi = refract_local(wo, m, eta)?;
+        let weight = (vec3![1., 1., 1.] - f) / (1. - reflect_probability);
+        (wi.z < 0.).then_some((wi, weight))
+    }
+}
+
+/// Reflection off of the microfacets, for a pair of directions instead of a
+/// random one. Returns the BSDF times the cosine (`F * D * G2 / (4 * cos_o)`),
+/// with `fresnel` evaluated at the angle between `wo` and the half vector.
+pub fn eval_microfacet_reflection(
+    ggx: &Ggx,
+    wo: Vec3,
+    wi: Vec3,
+    fresnel: impl Fn(f64) -> Color,
+) -> Color {
+    if wo.z <= 0. || wi.z <= 0. {
+        return Color::default();
+    }
+    let m = (wo + wi).unit_vec();
+    ggx.d(m) * ggx.g2(wo, wi) / (4. * wo.z) * fresnel(wo.dot(m))
+}
+
+/// The pdf of reflecting `wo` into `wi` off of a visible normal. A facet
+/// normal turns into twice the angle when we reflect off of it, which is where
+/// the `1 / (4 * cos)` comes from.
+pub fn pdf_microfacet_reflection(ggx: &Ggx, wo: Vec3, wi: Vec3) -> f64 {
+    if wo.z <= 0. || wi.z <= 0. {
+        return 0.;
+    }
+    let m = (wo + wi).unit_vec();
+    ggx.pdf_visible_normal(wo, m) / (4. * wo.dot(m).abs().max(1e-12))
+}
+
+/// The microfacet normal that bends `wo` into `wi` through a dielectric, i.e.
+/// the generalized half vector `-(wo + eta * wi)`, flipped to the outside.
+/// Also returns the jacobian `|dm / dwi|` that turns a pdf over facet normals
+/// into one over refracted directions.
+///
+/// `None` if no facet can do that (e.g. it would have to face away from `wo`).
+fn refraction_half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<(Vec3, f64)> {
+    let m = -(wo + eta * wi);
+    if m.len_squared() < 1e-12 {
+        return None;
+    }
+    let m = m.unit_vec();
+    let m = if m.z < 0. { -m } else { m };
+
+    if wo.dot(m) <= 0. || wi.dot(m) >= 0. {
+        return None;
+    }
+    let denominator = wo.dot(m) + eta * wi.dot(m);
+    let jacobian = eta * eta * wi.dot(m).abs() / (denominator * denominator);
+    Some((m, jacobian))
+}
+
+/// Same as `eval_microfacet_reflection`, but through a rough dielectric, so
+/// `wi` can be on either side. Matches the weights that
+/// `sample_rough_dielectric_with` gives out, so it doesn't do the `1 / eta^2`
+/// that radiance gets when it's squeezed into a denser material either.
+pub fn eval_rough_dielectric(
+    ggx: &Ggx,
+    wo: Vec3,
+    wi: Vec3,
+    eta: f64,
+    fresnel: impl Fn(f64) -> Color,
+) -> Color {
+    if wo.z <= 0. || wi.z == 0. {
+        return Color::default();
+    }
+    if wi.z > 0. {
+        return eval_microfacet_reflection(ggx, wo, wi, fresnel);
+    }
+
+    let Some((m, jacobian)) = refraction_half_vector(wo, wi, eta) else {
+        return Color::default();
+    };
+    let transmitted = vec3![1., 1., 1.] - fresnel(wo.dot(m));
+    ggx.d(m) * ggx.g2(wo, wi) * wo.dot(m) * jacobian / wo.z * transmitted
+}
+
+/// The pdf of `sample_rough_dielectric_with` picking `wi`, with the same
+/// `fresnel` (it chooses between reflecting and refracting with the average
+/// of it)
+pub fn pdf_rough_dielectric(
+    ggx: &Ggx,
+    wo: Vec3,
+    wi: Vec3,
+    eta: f64,
+    fresnel: impl Fn(f64) -> Color,
+) -> f64 {
+    if wo.z <= 0. || wi.z == 0. {
+        return 0.;
+    }
+    let average = |c: Color| (c.x + c.y + c.z) / 3.;
+
+    if wi.z > 0. {
+        let m = (wo + wi).unit_vec();
+        return average(fresnel(wo.dot(m))) * pdf_microfacet_reflection(ggx, wo, wi);
+    }
+
+    let Some((m, jacobian)) = refraction_half_vector(wo, wi, eta) else {
+        return 0.;
+    };
+    (1. - average(fresnel(wo.dot(m)))) * ggx.pdf_visible_normal(wo, m) * jacobian
+}
+
+/// How much light a metal reflects, from the full fresnel equations for a
+/// conductor. Metals absorb light really quickly, which is what the imaginary
+/// part of the index of refraction (`k`) describes, and since both `eta` and
+/// `k` change with wavelength, that's where the color of gold/copper comes
+/// from.
+///
+/// Done separately for every channel, see pbrt's `FrConductor`
+pub fn fresnel_conductor(cos_theta_i: f64, eta: Color, k: Color) -> Color {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i.clamp(0., 1.).powi(2);
+        let sin2 = 1. - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_theta_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    vec3![
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z)
+    ]
+}
+
+/// Metals with measured complex indices of refraction, sampled at roughly
+/// red/green/blue wavelengths (650/550/450nm)
+#[derive(Debug, Clone, Copy)]
+pub enum ConductorPreset {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Chrome,
+}
+
+impl ConductorPreset {
+    /// `(eta, k)`
+    pub fn ior(&self) -> (Color, Color) {
+        match self {
+            ConductorPreset::Gold => (vec3![0.143, 0.375, 1.442], vec3![3.983, 2.386, 1.603]),
+            ConductorPreset::Silver => (vec3![0.155, 0.117, 0.138], vec3![4.828, 3.122, 2.147]),
+            ConductorPreset::Copper => (vec3![0.200, 0.924, 1.102], vec3![3.913, 2.453, 2.142]),
+            ConductorPreset::Aluminium => (vec3![1.657, 0.880, 0.521], vec3![9.224, 6.270, 4.837]),
+            ConductorPreset::Chrome => (vec3![4.370, 2.917, 1.655], vec3![5.206, 4.231, 3.755]),
+        }
+    }
+}
+
+/// How many samples the monte carlo tests take, here and in `material`
+#[cfg(test)]
+pub(crate) const SAMPLES: usize = 200_000;
+
+/// About `SAMPLES` directions uniformly spread over the sphere, one jittered
+/// direction per cell of a grid over z and phi (each cell has the same area).
+/// A lot less noisy than purely random directions for integrating the narrow
+/// lobes in the tests.
+#[cfg(test)]
+pub(crate) fn sphere_directions() -> Vec<Vec3> {
+    let n = (SAMPLES as f64).sqrt() as usize;
+    let mut directions = Vec::with_capacity(n * n);
+    for i in 0..n {
+        for j in 0..n {
+            let z = -1. + 2. * (i as f64 + random_double(0.0..1.0)) / n as f64;
+            let phi = 2. * PI * (j as f64 + random_double(0.0..1.0)) / n as f64;
+            let r = (1. - z * z).max(0.).sqrt();
+            directions.push(vec3![r * phi.cos(), r * phi.sin(), z]);
+        }
+    }
+    directions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integral of `f` over the directions where `region` is true
+    fn integrate(f: impl Fn(Vec3) -> f64, region: impl Fn(Vec3) -> bool) -> f64 {
+        let directions = sphere_directions();
+        let total: f64 = directions
+            .iter()
+            .filter(|&&w| region(w))
+            .map(|&w| f(w))
+            .sum();
+        4. * PI * total / directions.len() as f64
+    }
+
+    /// How often `sample` lands in `region`
+    fn fraction(sample: impl Fn() -> Option<Vec3>, region: impl Fn(Vec3) -> bool) -> f64 {
+        let hits = (0..SAMPLES)
+            .filter(|_| sample().is_some_and(&region))
+            .count();
+        hits as f64 / SAMPLES as f64
+    }
+
+    fn wo() -> Vec3 {
+        vec3![0.5, -0.3, 0.6].unit_vec()
+    }
+
+    #[test]
+    fn visible_normals_integrate_to_one() {
+        for ggx in [Ggx::isotropic(0.3), Ggx::anisotropic(0.7, 0.2)] {
+            let total = integrate(|m| ggx.pdf_visible_normal(wo(), m), |m| m.z > 0.);
+            assert!((total - 1.).abs() < 0.02, "integrates to {total}");
+        }
+    }
+
+    #[test]
+    fn reflection_pdf_matches_sampling() {
+        let ggx = Ggx::anisotropic(0.6, 0.3);
+        let sample = || {
+            let m =
+                ggx.sample_visible_normal(wo(), random_double(0.0..1.0), random_double(0.0..1.0));
+            let wi = reflect_local(wo(), m);
+            (wi.z > 0.).then_some(wi)
+        };
+        let pdf = |wi| pdf_microfacet_reflection(&ggx, wo(), wi);
+
+        // everything that doesn't go below the surface, and a smaller patch
+        let regions: [fn(Vec3) -> bool; 2] = [|w| w.z > 0., |w| w.z > 0.5 && w.x < 0.];
+        for region in regions {
+            let expected = integrate(pdf, region);
+            let got = fraction(sample, region);
+            assert!(
+                (expected - got).abs() < 0.01,
+                "pdf says {expected}, sampling gives {got}"
+            );
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_pdf_matches_sampling() {
+        let ggx = Ggx::isotropic(0.5);
+        // going in, and coming back out (which has total internal reflection)
+        for eta in [1.5, 1. / 1.5] {
+            let fresnel = |cos_theta_i| {
+                let f = fresnel_dielectric(cos_theta_i, eta);
+                vec3![f, f, f]
+            };
+            let sample = || sample_rough_dielectric(&ggx, wo(), eta);
+            let pdf = |wi| pdf_rough_dielectric(&ggx, wo(), wi, eta, fresnel);
+
+            // whatever comes out at all, and then only the refracted part
+            let regions: [fn(Vec3) -> bool; 2] = [|_| true, |w| w.z < 0.];
+            for region in regions {
+                let expected = integrate(pdf, region);
+                let got = fraction(sample, region);
+                assert!(
+                    (expected - got).abs() < 0.01,
+                    "pdf says {expected}, sampling gives {got} (eta {eta})"
+                );
+            }
+        }
+    }
+}
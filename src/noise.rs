@@ -0,0 +1,335 @@
+use std::fmt;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::color::Color;
+use crate::texture::Texture;
+use crate::vec::*;
+use crate::{lerp, vec3};
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient (i.e. "real") perlin noise. Every integer lattice point gets a
+/// random unit vector, and the noise at a point is the smoothed blend of the
+/// dot products between those vectors and the offsets to the corners of the
+/// cell we're in. Gives back something roughly in -1 to 1.
+///
+/// Everything is decided by `seed`, so the same seed gives the exact same
+/// noise every render.
+pub struct Perlin {
+    pub seed: u64,
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+// the tables are just noise, the seed is what actually describes it
+impl fmt::Debug for Perlin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Perlin")
+            .field("seed", &self.seed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                vec3![
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0)
+                ]
+                .unit_vec()
+            })
+            .collect();
+
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        let perm_x = permutation();
+        let perm_y = permutation();
+        let perm_z = permutation();
+
+        Self {
+            seed,
+            gradients,
+            perm_x,
+            perm_y,
+            perm_z,
+        }
+    }
+
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let (i, j, k) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - i, p.y - j, p.z - k);
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+
+        // hermite cubic, so that the blend doesn't have visible creases at the
+        // cell borders
+        let (uu, vv, ww) = (smooth(u), smooth(v), smooth(w));
+
+        let mut accum = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.hash(i + di, j + dj, k + dk)];
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = vec3![u - fi, v - fj, w - fk];
+
+                    accum += (fi * uu + (1. - fi) * (1. - uu))
+                        * (fj * vv + (1. - fj) * (1. - vv))
+                        * (fk * ww + (1. - fk) * (1. - ww))
+                        * gradient.dot(weight);
+                }
+            }
+        }
+
+        accum
+    }
+
+    /// Which gradient belongs to the lattice point `(i, j, k)`
+    fn hash(&self, i: i64, j: i64, k: i64) -> usize {
+        let wrap = |n: i64| n.rem_euclid(POINT_COUNT as i64) as usize;
+        self.perm_x[wrap(i)] ^ self.perm_y[wrap(j)] ^ self.perm_z[wrap(k)]
+    }
+
+    /// Fractal brownian motion: add up `octaves` layers of noise, each one
+    /// twice the frequency and half the strength of the last one. Keeps the
+    /// sign, so it's still roughly -1 to 1.
+    pub fn fbm(&self, p: &Point3, octaves: u32) -> f64 {
+        let mut accum = 0.;
+        let mut p = *p;
+        let mut weight = 1.;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&p);
+            weight *= 0.5;
+            p *= 2.;
+        }
+
+        accum
+    }
+
+    /// Same as `fbm`, but with the absolute value of every layer, which gives
+    /// the sharp creases you see in marble veins and flames. 0 and up.
+    pub fn turbulence(&self, p: &Point3, octaves: u32) -> f64 {
+        let mut accum = 0.;
+        let mut p = *p;
+        let mut weight = 1.;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&p).abs();
+            weight *= 0.5;
+            p *= 2.;
+        }
+
+        accum
+    }
+}
+
+fn smooth(t: f64) -> f64 {
+    t * t * (3. - 2. * t)
+}
+
+/// Stripes along z, pushed around by turbulence so they turn into veins
+#[derive(Debug)]
+pub struct Marble {
+    pub noise: Perlin,
+    /// frequency of the stripes
+    pub scale: f64,
+    /// how much the turbulence bends the stripes
+    pub turbulence: f64,
+    pub octaves: u32,
+    pub base: Color,
+    pub vein: Color,
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let turbulence = self.turbulence * self.noise.turbulence(&(self.scale * *p), self.octaves);
+        let t = 0.5 * (1. + (self.scale * p.z + turbulence).sin());
+        lerp(self.vein, self.base, t)
+    }
+}
+
+/// Concentric rings around the y axis (i.e. the trunk of the tree), wobbled a
+/// bit with noise
+#[derive(Debug)]
+pub struct Wood {
+    pub noise: Perlin,
+    /// frequency of the noise
+    pub scale: f64,
+    /// how many rings per unit of distance from the center
+    pub rings: f64,
+    /// how much the noise wobbles the rings
+    pub turbulence: f64,
+    pub octaves: u32,
+    pub light: Color,
+    pub dark: Color,
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let distance = (p.x * p.x + p.z * p.z).sqrt();
+        let wobble = self.turbulence * self.noise.fbm(&(self.scale * *p), self.octaves);
+        let ring = (distance * self.rings + wobble).rem_euclid(1.);
+        // sharpen it up a bit, so the dark part of the ring is thinner
+        lerp(self.light, self.dark, ring.powi(3))
+    }
+}
+
+/// Soft blobs of `cloud` over `sky`
+#[derive(Debug)]
+pub struct Clouds {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: u32,
+    /// 0 is a clear sky, 1 is completely overcast
+    pub coverage: f64,
+    pub sky: Color,
+    pub cloud: Color,
+}
+
+impl Texture for Clouds {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        // fbm is roughly -1 to 1, move it to 0 to 1
+        let density = 0.5 * (1. + self.noise.fbm(&(self.scale * *p), self.octaves));
+        // a coverage of 0 only lets the very densest parts through
+        let t = ((density - (1. - self.coverage)) / 0.25).clamp(0., 1.);
+        lerp(self.sky, self.cloud, smooth(t))
+    }
+}
+
+/// Turns a lattice cell into a pseudo-random number from 0 to 1. Same inputs
+/// always give the same output, which is what lets cellular noise (and
+/// anything else that needs "random but fixed per cell") work without storing
+/// any tables.
+pub fn cell_random(seed: u64, i: i64, j: i64, k: i64) -> f64 {
+    // splitmix64 over all of the inputs mashed together
+    let mut x = seed
+        ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (j as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (k as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+
+    // top 53 bits, since that's all an f64 can hold
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Cellular (a.k.a. worley, a.k.a. voronoi) noise. Every integer cell gets one
+/// random feature point, and we measure how far away the closest few are.
+#[derive(Debug, Clone)]
+pub struct Worley {
+    pub seed: u64,
+}
+
+/// The result of looking up worley noise at a point
+#[derive(Debug, Clone, Copy)]
+pub struct WorleySample {
+    /// distance to the closest feature point
+    pub f1: f64,
+    /// distance to the second closest feature point
+    pub f2: f64,
+    /// which cell the closest feature point belongs to, so that you can give
+    /// every cell its own color
+    pub cell: (i64, i64, i64),
+}
+
+impl Worley {
+    fn feature_point(&self, i: i64, j: i64, k: i64) -> Point3 {
+        vec3![
+            i as f64 + cell_random(self.seed, i, j, k),
+            j as f64 + cell_random(self.seed.wrapping_add(1), i, j, k),
+            k as f64 + cell_random(self.seed.wrapping_add(2), i, j, k)
+        ]
+    }
+
+    pub fn sample(&self, p: &Point3) -> WorleySample {
+        let (i, j, k) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+
+        let mut f1 = f64::MAX;
+        let mut f2 = f64::MAX;
+        let mut cell = (i, j, k);
+
+        // the point in our own cell is at most sqrt(3) away, and points 3 cells
+        // over are at least 2 away, so 2 cells in every direction always has
+        // the closest one. The second closest can in theory be further than
+        // that, but only if almost every point around us is bunched up far
+        // away, which basically never happens.
+        for di in -2..=2 {
+            for dj in -2..=2 {
+                for dk in -2..=2 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let distance = (self.feature_point(ci, cj, ck) - *p).len();
+
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                        cell = (ci, cj, ck);
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+
+        WorleySample { f1, f2, cell }
+    }
+}
+
+/// Which part of the worley sample to turn into a color
+#[derive(Debug, Clone, Copy)]
+pub enum WorleyOutput {
+    /// round blobs, dark in the middle of each cell
+    F1,
+    /// a more bulgy, pillowy look
+    F2,
+    /// 0 exactly on the border between two cells, so it's great for cracks and
+    /// the grout between stones
+    F2MinusF1,
+}
+
+/// Blends between `near` and `far` based on the worley distance. With
+/// `variation` above 0, every cell also gets its brightness nudged a bit, so
+/// the stones aren't all the exact same color.
+#[derive(Debug, Clone)]
+pub struct Voronoi {
+    pub worley: Worley,
+    pub scale: f64,
+    pub output: WorleyOutput,
+    pub near: Color,
+    pub far: Color,
+    pub variation: f64,
+}
+
+impl Texture for Voronoi {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let sample = self.worley.sample(&(self.scale * *p));
+        let distance = match self.output {
+            WorleyOutput::F1 => sample.f1,
+            WorleyOutput::F2 => sample.f2,
+            WorleyOutput::F2MinusF1 => sample.f2 - sample.f1,
+        };
+
+        let (i, j, k) = sample.cell;
+        // not the same seed as the feature points, otherwise the brightness
+        // would follow where the point is in the cell
+        let cell_seed = self.worley.seed.wrapping_add(3);
+        let tint = 1. + self.variation * (2. * cell_random(cell_seed, i, j, k) - 1.);
+
+        // distances between neighboring points are around 1, so this is
+        // roughly 0 to 1 already
+        tint * lerp(self.near, self.far, distance.clamp(0., 1.))
+    }
+}
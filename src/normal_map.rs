@@ -0,0 +1,85 @@
+use std::rc::Rc;
+
+use crate::hittable::HitRecord;
+use crate::texture::Texture;
+use crate::vec3;
+
+/// How far over (in uv) we look to figure out the slope of a bump map
+const BUMP_DELTA: f64 = 1e-3;
+
+/// Fakes small bumps and dents on a surface by bending the normal that the
+/// material sees, without actually changing the geometry.
+#[derive(Debug, Clone)]
+pub enum NormalMap {
+    /// The usual blue-ish normal map image: each pixel's rgb is the xyz of the
+    /// new normal, squished from -1..1 into 0..1. x goes along the tangent, y
+    /// along the bitangent and z straight out of the surface. Load it with
+    /// `ImageTexture::load_data` so it doesn't get converted like a color.
+    TangentSpace {
+        texture: Rc<dyn Texture>,
+        /// 0 is a flat surface, 1 is the normal map as is
+        strength: f64,
+    },
+    /// A grayscale height map, the normal gets tilted towards whichever way
+    /// the height goes down
+    Bump {
+        height: Rc<dyn Texture>,
+        /// how tall the bumps are, bigger is bumpier
+        strength: f64,
+    },
+}
+
+/// Bends `hit_record.normal` according to the normal map. The geometric normal
+/// is left alone.
+pub fn perturb_normal(normal_map: &NormalMap, hit_record: &mut HitRecord) {
+    let (u, v, p) = (hit_record.u, hit_record.v, hit_record.p);
+
+    // the tangent frame has to be built around the *outward* normal, otherwise
+    // the map would get turned inside out whenever we hit the back of a surface
+    let outward_normal = if hit_record.front_face {
+        hit_record.geometric_normal
+    } else {
+        -hit_record.geometric_normal
+    };
+    let tangent =
+        (hit_record.tangent - hit_record.tangent.dot(outward_normal) * outward_normal).unit_vec();
+    let bitangent = outward_normal.cross(tangent);
+
+    let local = match normal_map {
+        NormalMap::TangentSpace { texture, strength } => {
+            let c = texture.value(u, v, &p);
+            let n = vec3![2. * c.x - 1., 2. * c.y - 1., 2. * c.z - 1.];
+            // shrink the sideways part for a weaker effect
+            vec3![strength * n.x, strength * n.y, n.z]
+        }
+        NormalMap::Bump { height, strength } => {
+            // slope of the height map along u and v. p gets nudged along too,
+            // so that 3D textures (marble, noise) work as bump maps as well
+            let h = height.value(u, v, &p).x;
+            let h_u = height
+                .value(u + BUMP_DELTA, v, &(p + BUMP_DELTA * tangent))
+                .x;
+            let h_v = height
+                .value(u, v + BUMP_DELTA, &(p + BUMP_DELTA * bitangent))
+                .x;
+            let dh_du = (h_u - h) / BUMP_DELTA;
+            let dh_dv = (h_v - h) / BUMP_DELTA;
+
+            vec3![-strength * dh_du, -strength * dh_dv, 1.]
+        }
+    };
+
+    if local.len_squared() < 1e-12 {
+        return;
+    }
+    let local = local.unit_vec();
+
+    let outward_shading_normal =
+        (local.x * tangent + local.y * bitangent + local.z * outward_normal).unit_vec();
+
+    hit_record.normal = if hit_record.front_face {
+        outward_shading_normal
+    } else {
+        -outward_shading_normal
+    };
+}
@@ -0,0 +1,323 @@
+use std::f64::consts::PI;
+
+use crate::camera::random_cosine_direction;
+use crate::color::{luminance, Color};
+use crate::microfacet::{
+    eval_microfacet_reflection, eval_rough_dielectric, fresnel_dielectric,
+    pdf_microfacet_reflection, pdf_rough_dielectric, reflect_local, sample_rough_dielectric, Ggx,
+};
+use crate::random_double;
+use crate::ray::RayType;
+use crate::vec::*;
+use crate::{lerp, vec3};
+
+/// The clearcoat is always a plain ior 1.5 coat, which reflects 4% head on
+const CLEARCOAT_F0: Color = Vec3 {
+    x: 0.04,
+    y: 0.04,
+    z: 0.04,
+};
+
+/// One material to rule them all, loosely following Disney's "principled"
+/// BRDF (Burley 2012, plus the transmission from 2015). Every knob goes from 0
+/// to 1 unless it says otherwise, so it maps pretty much straight onto glTF's
+/// metallic-roughness and MTL's parameters.
+///
+/// The base color is the material's `albedo`, so it can be textured.
+///
+/// Under the hood it's a mix of a couple of lobes (diffuse + sheen, specular,
+/// clearcoat and transmission), and every time we scatter we randomly pick one
+/// of them to sample.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    /// 0 is a dielectric (plastic, wood), 1 is a metal
+    pub metallic: f64,
+    pub roughness: f64,
+    /// How strong the highlight is on dielectrics. 0.5 is the usual 4%
+    /// reflectance (an ior of 1.5)
+    pub specular: f64,
+    /// Tints the dielectric highlight towards the base color
+    pub specular_tint: f64,
+    /// Extra soft glow at grazing angles, for cloth
+    pub sheen: f64,
+    /// Tints the sheen towards the base color
+    pub sheen_tint: f64,
+    /// A second, clear, white highlight on top, like a coat of lacquer
+    pub clearcoat: f64,
+    /// 0 is a satin clearcoat, 1 is glossy
+    pub clearcoat_gloss: f64,
+    /// How much light goes through the (dielectric part of the) surface,
+    /// like glass
+    pub transmission: f64,
+    /// Only used for transmission
+    pub ior: f64,
+    /// Light given off by the surface, can go above 1
+    pub emission: Color,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.,
+            sheen: 0.,
+            sheen_tint: 0.5,
+            clearcoat: 0.,
+            clearcoat_gloss: 1.,
+            transmission: 0.,
+            ior: 1.5,
+            emission: vec3![0., 0., 0.],
+        }
+    }
+}
+
+/// Result of sampling one of the lobes, in local space
+pub struct PrincipledSample {
+    pub wi: Vec3,
+    pub weight: Color,
+    pub ray_type: RayType,
+}
+
+impl Principled {
+    /// How much each lobe counts for. These add up to more than 1, since the
+    /// specular and clearcoat sit on top of the diffuse.
+    fn lobe_weights(&self) -> [f64; 4] {
+        let diffuse = (1. - self.metallic) * (1. - self.transmission);
+        let transmission = (1. - self.metallic) * self.transmission;
+        // glass does its own reflection, so the plain specular lobe only
+        // covers the part that isn't glass
+        let specular = 1. - transmission;
+        let clearcoat = 0.25 * self.clearcoat;
+
+        [diffuse, specular, clearcoat, transmission]
+    }
+
+    /// `wo` points away from the surface, in local space (normal is +z).
+    /// `entering` is whether we're on the outside of the object, which only
+    /// matters for transmission.
+    pub fn sample(&self, base_color: Color, wo: Vec3, entering: bool) -> Option<PrincipledSample> {
+        if wo.z <= 0. {
+            return None;
+        }
+
+        // pick a lobe with probability proportional to how much it counts
+        // for, and then divide by that probability again so that on average
+        // we get the sum of all of them
+        let weights = self.lobe_weights();
+        let total: f64 = weights.iter().sum();
+        let mut pick = random_double(0.0..1.0) * total;
+        let lobe = weights
+            .iter()
+            .position(|&w| {
+                pick -= w;
+                pick < 0.
+            })
+            .unwrap_or(0);
+
+        let mut sample = match lobe {
+            0 => self.sample_diffuse(base_color, wo),
+            1 => self.sample_specular(base_color, wo),
+            2 => self.sample_clearcoat(wo),
+            _ => self.sample_transmission(base_color, wo, entering),
+        }?;
+
+        // weight / probability = weight / (weight / total)
+        sample.weight = total * sample.weight;
+        Some(sample)
+    }
+
+    /// Lambert, but with burley's retro-reflection at grazing angles for rough
+    /// surfaces, plus the sheen
+    fn sample_diffuse(&self, base_color: Color, wo: Vec3) -> Option<PrincipledSample> {
+        let wi = random_cosine_direction();
+
+        // cosine sampling cancels the cos / pi
+        Some(PrincipledSample {
+            wi,
+            weight: self.diffuse(base_color, wo, wi),
+            ray_type: RayType::Diffuse,
+        })
+    }
+
+    /// The diffuse + sheen lobe times pi, i.e. without lambert's 1 / pi
+    fn diffuse(&self, base_color: Color, wo: Vec3, wi: Vec3) -> Color {
+        let h = (wo + wi).unit_vec();
+        let cos_d = wi.dot(h);
+
+        let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+        let fd =
+            (1. + (fd90 - 1.) * schlick_weight(wi.z)) * (1. + (fd90 - 1.) * schlick_weight(wo.z));
+
+        let sheen_color = lerp(vec3![1., 1., 1.], tint(base_color), self.sheen_tint);
+        let sheen = self.sheen * schlick_weight(cos_d) * sheen_color;
+
+        // the sheen has no 1 / pi in it to begin with
+        fd * base_color + PI * sheen
+    }
+
+    fn sample_specular(&self, base_color: Color, wo: Vec3) -> Option<PrincipledSample> {
+        let ggx = Ggx::isotropic(self.roughness);
+        let m = ggx.sample_visible_normal(wo, random_double(0.0..1.0), random_double(0.0..1.0));
+        let wi = reflect_local(wo, m);
+        if wi.z <= 0. {
+            return None;
+        }
+
+        Some(PrincipledSample {
+            wi,
+            weight: ggx.g2(wo, wi) / ggx.g1(wo) * schlick(self.f0(base_color), wo.dot(m)),
+            ray_type: RayType::Specular,
+        })
+    }
+
+    /// Disney uses a different distribution (GTR1) for the clearcoat, we just
+    /// use a really smooth GGX, which looks about the same
+    fn sample_clearcoat(&self, wo: Vec3) -> Option<PrincipledSample> {
+        let ggx = self.clearcoat_ggx();
+        let m = ggx.sample_visible_normal(wo, random_double(0.0..1.0), random_double(0.0..1.0));
+        let wi = reflect_local(wo, m);
+        if wi.z <= 0. {
+            return None;
+        }
+
+        let f = schlick(CLEARCOAT_F0, wo.dot(m));
+
+        Some(PrincipledSample {
+            wi,
+            weight: ggx.g2(wo, wi) / ggx.g1(wo) * f,
+            ray_type: RayType::Specular,
+        })
+    }
+
+    fn sample_transmission(
+        &self,
+        base_color: Color,
+        wo: Vec3,
+        entering: bool,
+    ) -> Option<PrincipledSample> {
+        let ggx = Ggx::isotropic(self.roughness);
+        let wi = sample_rough_dielectric(&ggx, wo, self.eta(entering))?;
+
+        // only the light that actually goes through gets tinted
+        let tint = if wi.z < 0. {
+            base_color
+        } else {
+            vec3![1., 1., 1.]
+        };
+
+        Some(PrincipledSample {
+            wi,
+            weight: ggx.g2(wo, wi) / ggx.g1(wo) * tint,
+            ray_type: RayType::Specular,
+        })
+    }
+
+    /// Specular color at normal incidence
+    fn f0(&self, base_color: Color) -> Color {
+        let dielectric_f0 =
+            0.08 * self.specular * lerp(vec3![1., 1., 1.], tint(base_color), self.specular_tint);
+        lerp(dielectric_f0, base_color, self.metallic)
+    }
+
+    fn clearcoat_ggx(&self) -> Ggx {
+        Ggx::isotropic((1. - self.clearcoat_gloss) * 0.3 + 0.03)
+    }
+
+    /// ior of the side we're going into over the side we're coming from
+    fn eta(&self, entering: bool) -> f64 {
+        if entering {
+            self.ior
+        } else {
+            1. / self.ior
+        }
+    }
+
+    /// All of the lobes added up for light going from `wi` to `wo` (in local
+    /// space), times the cosine. Same arguments as `sample`.
+    pub fn eval(&self, base_color: Color, wo: Vec3, wi: Vec3, entering: bool) -> Color {
+        if wo.z <= 0. {
+            return Color::default();
+        }
+        let [diffuse, specular, clearcoat, transmission] = self.lobe_weights();
+        let ggx = Ggx::isotropic(self.roughness);
+        let eta = self.eta(entering);
+
+        // everything but the transmission only reflects
+        let reflected = if wi.z > 0. {
+            diffuse * wi.z / PI * self.diffuse(base_color, wo, wi)
+                + specular
+                    * eval_microfacet_reflection(&ggx, wo, wi, |cos| {
+                        schlick(self.f0(base_color), cos)
+                    })
+                + clearcoat
+                    * eval_microfacet_reflection(&self.clearcoat_ggx(), wo, wi, |cos| {
+                        schlick(CLEARCOAT_F0, cos)
+                    })
+        } else {
+            Color::default()
+        };
+
+        // only the light that actually goes through gets tinted
+        let tint = if wi.z < 0. {
+            base_color
+        } else {
+            vec3![1., 1., 1.]
+        };
+        reflected
+            + transmission
+                * eval_rough_dielectric(&ggx, wo, wi, eta, dielectric_fresnel(eta))
+                * tint
+    }
+
+    /// The pdf of `sample` picking `wi`, which is the pdf of each lobe
+    /// weighted by how likely we are to pick it
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, entering: bool) -> f64 {
+        if wo.z <= 0. {
+            return 0.;
+        }
+        let weights = self.lobe_weights();
+        let total: f64 = weights.iter().sum();
+        let [diffuse, specular, clearcoat, transmission] = weights.map(|w| w / total);
+        let ggx = Ggx::isotropic(self.roughness);
+        let eta = self.eta(entering);
+
+        let mut pdf = 0.;
+        if wi.z > 0. {
+            pdf += diffuse * wi.z / PI;
+            pdf += specular * pdf_microfacet_reflection(&ggx, wo, wi);
+            pdf += clearcoat * pdf_microfacet_reflection(&self.clearcoat_ggx(), wo, wi);
+        }
+        pdf + transmission * pdf_rough_dielectric(&ggx, wo, wi, eta, dielectric_fresnel(eta))
+    }
+}
+
+/// `fresnel_dielectric` for every channel, in the shape that the rough
+/// dielectric functions want
+fn dielectric_fresnel(eta: f64) -> impl Fn(f64) -> Color {
+    move |cos| {
+        let f = fresnel_dielectric(cos, eta);
+        vec3![f, f, f]
+    }
+}
+
+/// The base color's hue and saturation, but with the brightness taken out
+fn tint(base_color: Color) -> Color {
+    let l = luminance(base_color);
+    if l > 0. {
+        base_color / l
+    } else {
+        vec3![1., 1., 1.]
+    }
+}
+
+/// `(1 - cos)^5`, the part of schlick's approximation that goes up at grazing
+/// angles
+fn schlick_weight(cos: f64) -> f64 {
+    (1. - cos).clamp(0., 1.).powi(5)
+}
+
+fn schlick(f0: Color, cos: f64) -> Color {
+    f0 + schlick_weight(cos) * (vec3![1., 1., 1.] - f0)
+}
@@ -1,11 +1,27 @@
-use std::f64::MAX;
+use crate::vec::*;
 
-use crate::vec3;
-use crate::{color::Color, vec::*, Scene};
+/// What a ray is being traced for. Objects can opt out of being seen by some of
+/// these (see `Visibility`), which is how you get things like a light card that
+/// the camera can't see, or a sphere that doesn't show up in reflections.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RayType {
+    /// Primary rays shot out of the camera through a pixel
+    #[default]
+    Camera,
+    /// Mirror-like bounces, i.e. reflection and refraction
+    Specular,
+    /// Scattered bounces off of rough/diffuse surfaces
+    Diffuse,
+    /// The random walk inside of a `Subsurface` object looking for its way
+    /// out. These always see everything, otherwise hiding an object from
+    /// some other kind of ray would let the walk leak out through it.
+    Subsurface,
+}
 
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub ray_type: RayType,
 }
 
 impl Ray {
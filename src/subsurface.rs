@@ -0,0 +1,165 @@
+use std::rc::Rc;
+
+use crate::camera::random_unit_vector;
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{reflect, reflectance, refract, Scatter};
+use crate::random_double;
+use crate::ray::{Ray, RayType};
+use crate::vec::*;
+use crate::{vec3, Scene};
+
+/// After this many bounces inside of the object we assume the light got
+/// absorbed
+const MAX_WALK_STEPS: u32 = 256;
+
+/// Where the inside of the object stops. Same idea as the `0.001` in
+/// `ray_color`, so the walk doesn't immediately find the surface it just
+/// entered through.
+const WALK_EPSILON: f64 = 1e-4;
+
+/// Light hitting a smooth dielectric boundary from the side that `normal`
+/// points to, either bounces back or goes through. `ratio` is the ior of the
+/// side we're on over the side we'd go into.
+fn cross_boundary(direction: &Vec3, normal: &Vec3, ratio: f64) -> Vec3 {
+    let unit_direction = direction.unit_vec();
+    let cos_theta = (-unit_direction).dot(*normal).min(1.);
+    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+
+    if ratio * sin_theta > 1. || reflectance(cos_theta, ratio) > random_double(0.0..1.0) {
+        reflect(&unit_direction, normal)
+    } else {
+        refract(&unit_direction, normal, ratio)
+    }
+}
+
+/// Subsurface scattering with a volumetric random walk: light refracts into
+/// the object, bounces around inside of it like it would in a fog, and then
+/// comes back out somewhere else (that's what makes skin and wax look soft).
+///
+/// - `mean_free_path`: how far light goes between bounces on average, per
+///   channel. Bigger is more see-through. Skin lets red go a lot further than
+///   blue, which is why backlit ears glow red.
+/// - `albedo`: the chance of light surviving each bounce inside of the object
+/// - `ior`: for the smooth boundary on the outside
+///
+/// The object has to be closed, since we use `Scene::hit` to find where the
+/// walk comes back out. Anything else that the walk runs into on the inside is
+/// ignored.
+pub fn scatter_subsurface(
+    mean_free_path: Color,
+    albedo: Color,
+    ior: f64,
+    ray_incident: &Vec3,
+    hit_record: &HitRecord,
+    scene: &mut Scene,
+) -> Option<Scatter> {
+    if !hit_record.front_face {
+        // started inside of the object somehow, just let it out
+        return Some(Scatter {
+            scattered: Ray {
+                origin: hit_record.p,
+                direction: cross_boundary(ray_incident, &hit_record.normal, ior),
+                ray_type: RayType::Diffuse,
+            },
+            attenuation: vec3![1., 1., 1.],
+        });
+    }
+
+    let mut direction = cross_boundary(ray_incident, &hit_record.normal, 1. / ior);
+    if direction.dot(hit_record.normal) > 0. {
+        // bounced off of the outside, never went in
+        return Some(Scatter {
+            scattered: Ray {
+                origin: hit_record.p,
+                direction,
+                ray_type: RayType::Specular,
+            },
+            attenuation: vec3![1., 1., 1.],
+        });
+    }
+
+    let sigma_t = vec3![
+        1. / mean_free_path.x.max(1e-6),
+        1. / mean_free_path.y.max(1e-6),
+        1. / mean_free_path.z.max(1e-6)
+    ];
+    let transmittance = |distance: f64| {
+        vec3![
+            (-sigma_t.x * distance).exp(),
+            (-sigma_t.y * distance).exp(),
+            (-sigma_t.z * distance).exp()
+        ]
+    };
+    let average = |c: Color| (c.x + c.y + c.z) / 3.;
+
+    let mut origin = hit_record.p;
+    let mut throughput = vec3![1., 1., 1.];
+
+    for _ in 0..MAX_WALK_STEPS {
+        // every channel has its own mean free path, so we pick one of them at
+        // random to decide how far to go, and then weigh by the average pdf of
+        // all 3 (i.e. one sample MIS over the channels)
+        let channel_sigma_t = match (random_double(0.0..3.0) as usize).min(2) {
+            0 => sigma_t.x,
+            1 => sigma_t.y,
+            _ => sigma_t.z,
+        };
+        let distance = -(1. - random_double(0.0..1.0)).ln() / channel_sigma_t;
+
+        let ray = Ray {
+            origin,
+            direction,
+            ray_type: RayType::Subsurface,
+        };
+
+        match scene.hit(&ray, WALK_EPSILON..distance) {
+            Some(exit) => {
+                // made it to the surface before bouncing. The chance of that
+                // is just the transmittance
+                let t = transmittance(exit.t);
+                throughput = throughput * t / average(t);
+
+                let own_surface = match (&exit.material, &hit_record.material) {
+                    (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                    _ => false,
+                };
+                if !own_surface || exit.front_face {
+                    // something else poking into the object (or going into
+                    // another part of it), which doesn't stop the walk. The
+                    // distance is memoryless, so we just start over from here.
+                    origin = exit.p;
+                    continue;
+                }
+
+                let next = cross_boundary(&direction, &exit.normal, ior);
+                if next.dot(exit.normal) < 0. {
+                    // made it out
+                    return Some(Scatter {
+                        scattered: Ray {
+                            origin: exit.p,
+                            direction: next,
+                            ray_type: RayType::Diffuse,
+                        },
+                        attenuation: throughput,
+                    });
+                }
+                // reflected back in off of the inside of the boundary
+                origin = exit.p;
+                direction = next;
+            }
+            None => {
+                // bounced off of something inside of the medium
+                let t = transmittance(distance);
+                let pdf = average(sigma_t * t);
+                throughput = throughput * albedo * sigma_t * t / pdf;
+
+                origin = ray.at(distance);
+                // isotropic, every direction is equally likely
+                direction = random_unit_vector();
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,131 @@
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use crate::color::Color;
+use crate::noise::cell_random;
+use crate::vec::*;
+
+/// Anything that can give back a color for a point on a surface. `(u, v)` are
+/// the surface coordinates of the hit (both from 0 to 1), and `p` is the hit
+/// point itself, for textures that live in 3D space instead of on the surface.
+///
+/// Unlike materials, this one actually is a trait, since textures nest inside
+/// of each other (a checker made of two other textures) and users will want to
+/// plug in their own.
+pub trait Texture: Debug {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+/// Shorthand for the most common texture, a single flat color
+pub fn solid(color: Color) -> Rc<dyn Texture> {
+    Rc::new(SolidColor { color })
+}
+
+#[derive(Debug, Clone)]
+pub struct SolidColor {
+    pub color: Color,
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+/// Checkerboard laid out over the surface's `(u, v)`, so it wraps around the
+/// object. `scale` is how many squares there are along each of u and v.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    pub scale: f64,
+    pub even: Rc<dyn Texture>,
+    pub odd: Rc<dyn Texture>,
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let u_integer = (u * self.scale).floor() as i64;
+        let v_integer = (v * self.scale).floor() as i64;
+
+        if (u_integer + v_integer) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// Checkerboard made out of 3D cubes of side length `scale` in world space,
+/// so it's as if the object was carved out of a block of checkers. Doesn't
+/// care about uvs at all.
+#[derive(Debug, Clone)]
+pub struct SolidChecker {
+    pub scale: f64,
+    pub even: Rc<dyn Texture>,
+    pub odd: Rc<dyn Texture>,
+}
+
+impl Texture for SolidChecker {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let x_integer = (p.x / self.scale).floor() as i64;
+        let y_integer = (p.y / self.scale).floor() as i64;
+        let z_integer = (p.z / self.scale).floor() as i64;
+
+        if (x_integer + y_integer + z_integer) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// Bricks/tiles laid out over the surface's `(u, v)`, with mortar between them.
+///
+/// Every tile gets its own slightly different color (`variation`), picked from
+/// `seed`, so a wall doesn't look like one brick copy pasted over and over.
+#[derive(Debug, Clone)]
+pub struct Tiles {
+    /// how many tiles fit along u
+    pub columns: f64,
+    /// how many tiles fit along v
+    pub rows: f64,
+    /// thickness of the mortar, as a fraction of a tile (0 to 0.5)
+    pub mortar_width: f64,
+    /// how far every other row is shifted over, as a fraction of a tile. 0.5
+    /// is the usual brick wall, 0 is a plain grid of tiles
+    pub row_offset: f64,
+    pub tile: Rc<dyn Texture>,
+    pub mortar: Rc<dyn Texture>,
+    /// how much the brightness can change from tile to tile, 0 to 1
+    pub variation: f64,
+    pub seed: u64,
+}
+
+impl Texture for Tiles {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let y = v * self.rows;
+        let row = y.floor();
+        // every other row gets shifted over
+        let shift = if (row as i64).rem_euclid(2) == 1 {
+            self.row_offset
+        } else {
+            0.
+        };
+        let x = u * self.columns + shift;
+        let column = x.floor();
+
+        // where we are inside of the tile, 0 to 1
+        let (tile_x, tile_y) = (x - column, y - row);
+        let half_mortar = self.mortar_width / 2.;
+        let in_mortar = tile_x < half_mortar
+            || tile_x > 1. - half_mortar
+            || tile_y < half_mortar
+            || tile_y > 1. - half_mortar;
+
+        if in_mortar {
+            self.mortar.value(u, v, p)
+        } else {
+            let random = cell_random(self.seed, column as i64, row as i64, 0);
+            (1. + self.variation * (2. * random - 1.)) * self.tile.value(u, v, p)
+        }
+    }
+}
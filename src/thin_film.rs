@@ -0,0 +1,167 @@
+use std::f64::consts::PI;
+use std::ops;
+
+use crate::color::Color;
+use crate::vec3;
+
+/// A really thin transparent layer on top of a surface (soap bubbles, oil on
+/// water, the anti-reflective coating on lenses).
+///
+/// Light bounces off of both the top and the bottom of the film, and since the
+/// film is about as thick as a wavelength of light, the two reflections line up
+/// or cancel out depending on the wavelength. That's where the rainbow colors
+/// come from.
+#[derive(Debug, Clone)]
+pub struct ThinFilm {
+    /// in nanometers, visible light is roughly 400-700nm
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+/// Wavelengths (in nm) that we average over for each of red, green and blue.
+/// Using just one wavelength per channel gives colors that are way too
+/// saturated.
+const WAVELENGTHS: [[f64; 3]; 3] = [[600., 640., 680.], [510., 550., 590.], [420., 460., 500.]];
+
+/// Just enough complex numbers for the fresnel equations
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn norm_squared(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(&self) -> Self {
+        let r = self.norm_squared().sqrt();
+        let re = ((r + self.re) / 2.).max(0.).sqrt();
+        let im = ((r - self.re) / 2.).max(0.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+
+    /// e^(i * self)
+    fn exp_i(&self) -> Self {
+        let magnitude = (-self.im).exp();
+        Self::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl ops::Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl ops::Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl ops::Div for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Complex) -> Complex {
+        let d = rhs.norm_squared();
+        Complex::new(
+            (self.re * rhs.re + self.im * rhs.im) / d,
+            (self.im * rhs.re - self.re * rhs.im) / d,
+        )
+    }
+}
+
+/// cos of the angle inside of a medium with ior `n_t`, from snell's law.
+/// Complex, since past the critical angle (or inside of a metal) there isn't a
+/// real one.
+fn cos_transmitted(sin2_i: f64, n_i: Complex, n_t: Complex) -> Complex {
+    let ratio = n_i / n_t;
+    (Complex::new(1., 0.) - ratio * ratio * Complex::new(sin2_i, 0.)).sqrt()
+}
+
+/// Fresnel amplitude (not intensity) coefficients, `(s, p)` polarized
+fn fresnel_amplitudes(
+    n_i: Complex,
+    cos_i: Complex,
+    n_t: Complex,
+    cos_t: Complex,
+) -> (Complex, Complex) {
+    let s = (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t);
+    let p = (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t);
+    (s, p)
+}
+
+/// How much light gets reflected by a surface with a thin film on it, from the
+/// airy summation of all of the bounces inside of the film.
+///
+/// - `cos_theta_i`: angle we're coming in at
+/// - `n_incident`: ior of what we're coming from (air ~= 1)
+/// - `eta` and `k`: complex ior of what's under the film, per channel. `k` is 0
+///   for dielectrics.
+pub fn thin_film_reflectance(
+    cos_theta_i: f64,
+    n_incident: f64,
+    film: &ThinFilm,
+    eta: Color,
+    k: Color,
+) -> Color {
+    let cos_theta_i = cos_theta_i.clamp(0., 1.);
+    let sin2_i = 1. - cos_theta_i * cos_theta_i;
+
+    let n1 = Complex::new(n_incident, 0.);
+    let n2 = Complex::new(film.ior, 0.);
+    let cos1 = Complex::new(cos_theta_i, 0.);
+    let cos2 = cos_transmitted(sin2_i, n1, n2);
+    let (r12_s, r12_p) = fresnel_amplitudes(n1, cos1, n2, cos2);
+
+    let channel = |eta: f64, k: f64, wavelengths: &[f64; 3]| {
+        let n3 = Complex::new(eta, k);
+        let cos3 = cos_transmitted(sin2_i, n1, n3);
+        let (r23_s, r23_p) = fresnel_amplitudes(n2, cos2, n3, cos3);
+
+        let total: f64 = wavelengths
+            .iter()
+            .map(|wavelength| {
+                // extra distance (well, phase) that the light bouncing off of
+                // the bottom of the film travels
+                let phase = Complex::new(4. * PI * film.thickness / wavelength, 0.) * n2 * cos2;
+                let shift = phase.exp_i();
+
+                let airy = |r12: Complex, r23: Complex| {
+                    ((r12 + r23 * shift) / (Complex::new(1., 0.) + r12 * r23 * shift))
+                        .norm_squared()
+                };
+                0.5 * (airy(r12_s, r23_s) + airy(r12_p, r23_p))
+            })
+            .sum();
+
+        (total / wavelengths.len() as f64).clamp(0., 1.)
+    };
+
+    vec3![
+        channel(eta.x, k.x, &WAVELENGTHS[0]),
+        channel(eta.y, k.y, &WAVELENGTHS[1]),
+        channel(eta.z, k.z, &WAVELENGTHS[2])
+    ]
+}
@@ -40,11 +40,24 @@ impl Vec3 {
     }
     pub fn cross(&self, rhs: Vec3) -> Vec3 {
         vec3![
-            self.y * rhs.z - rhs.y - self.z,
-            self.x * rhs.z - rhs.x * self.z,
-            self.x * rhs.y - rhs.x * self.y
+            self.y * rhs.z - self.z * rhs.y,
+            self.z * rhs.x - self.x * rhs.z,
+            self.x * rhs.y - self.y * rhs.x
         ]
     }
+    /// Some unit vector that's perpendicular to this one. Which one exactly
+    /// doesn't matter, for when we need a tangent but the surface doesn't have
+    /// an obvious one.
+    pub fn any_perpendicular(&self) -> Vec3 {
+        // cross with whichever axis is the least parallel to us, so that the
+        // result can't end up close to 0
+        let axis = if self.x.abs() < 0.9 {
+            vec3![1., 0., 0.]
+        } else {
+            vec3![0., 1., 0.]
+        };
+        self.cross(axis).unit_vec()
+    }
     pub fn clamp(&self, min: f64, max: f64) -> Vec3 {
         vec3![
             self.x.clamp(min, max),
@@ -54,6 +67,43 @@ impl Vec3 {
     }
 }
 
+/// Orthonormal basis, i.e. a little local coordinate system sitting on a
+/// surface. `w` is the normal, `u` follows the tangent and `v` is the third
+/// axis. Lots of shading math is way simpler when the normal is just +z.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(normal: Vec3, tangent: Vec3) -> Self {
+        let w = normal.unit_vec();
+        // the tangent might not be exactly perpendicular (e.g. after the normal
+        // got bent by a normal map), so straighten it out
+        let u = tangent - tangent.dot(w) * w;
+        let u = if u.len_squared() > 1e-12 {
+            u.unit_vec()
+        } else {
+            w.any_perpendicular()
+        };
+        let v = w.cross(u);
+
+        Self { u, v, w }
+    }
+
+    /// world space -> local space
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        vec3![a.dot(self.u), a.dot(self.v), a.dot(self.w)]
+    }
+
+    /// local space -> world space
+    pub fn to_world(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
+
 impl ops::Neg for Vec3 {
     type Output = Vec3;
 
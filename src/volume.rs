@@ -0,0 +1,317 @@
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::color::{luminance, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Scatter;
+use crate::random_double;
+use crate::ray::{Ray, RayType};
+use crate::texture::Texture;
+use crate::vec::*;
+use crate::vec3;
+use crate::voxel_grid::VoxelGrid;
+use crate::{Object, ObjectType};
+
+/// How far past the boundary's first hit we look for the second one
+const BOUNDARY_EPSILON: f64 = 1e-4;
+
+/// Which way light goes when it bounces off of a particle in a volume
+#[derive(Debug, Clone, Copy)]
+pub enum PhaseFunction {
+    /// Every direction is equally likely, fine for most smoke and fog
+    Isotropic,
+    /// `g` goes from -1 to 1. Positive keeps light going mostly the same way
+    /// it was going (clouds, haze), negative bounces it mostly back, 0 is the
+    /// same as `Isotropic`.
+    HenyeyGreenstein { g: f64 },
+}
+
+impl PhaseFunction {
+    /// Isotropic is just henyey greenstein with `g = 0`
+    fn g(&self) -> f64 {
+        match *self {
+            PhaseFunction::Isotropic => 0.,
+            PhaseFunction::HenyeyGreenstein { g } => g.clamp(-0.999, 0.999),
+        }
+    }
+
+    /// How likely light going along `direction` is to bounce into `wi`, per
+    /// steradian. Also the pdf of `sample`.
+    pub fn pdf(&self, direction: &Vec3, wi: &Vec3) -> f64 {
+        let g = self.g();
+        let cos_theta = direction.unit_vec().dot(wi.unit_vec());
+        let denominator = 1. + g * g - 2. * g * cos_theta;
+        (1. - g * g) / (4. * PI * denominator * denominator.sqrt())
+    }
+
+    /// Picks a new direction for light that was going along `direction`. The
+    /// phase function is its own pdf, so there's no weight to return.
+    pub fn sample(&self, direction: &Vec3) -> Vec3 {
+        let g = self.g();
+
+        // cos of the angle between the old and the new direction
+        let u = random_double(0.0..1.0);
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u
+        } else {
+            let s = (1. - g * g) / (1. - g + 2. * g * u);
+            (1. + g * g - s * s) / (2. * g)
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * random_double(0.0..1.0);
+
+        let forward = direction.unit_vec();
+        let frame = Onb::new(forward, forward.any_perpendicular());
+        frame.to_world(vec3![
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta
+        ])
+    }
+}
+
+/// A cloud of particles filling up a closed `boundary`, where every bit of
+/// distance the ray travels inside has the same chance of hitting a particle.
+///
+/// That makes the distance to the first particle exponentially distributed,
+/// so we pick one with `-ln(random) / density`. If it's further than the ray
+/// goes through the boundary, the ray made it through without hitting
+/// anything.
+///
+/// The hit is in the middle of the volume, so it doesn't really have a normal.
+/// We point it back at the ray, which keeps everything in `ray_color` happy.
+pub fn hit_constant_medium(
+    boundary: &ObjectType,
+    density: f64,
+    ray: &Ray,
+    ray_range: Range<f64>,
+) -> Option<HitRecord> {
+    // where the ray goes in and out of the boundary, even if that's behind it
+    // (i.e. the ray starts inside)
+    let enter = Object::hit(ray, f64::NEG_INFINITY..f64::INFINITY, boundary)?;
+    let exit = Object::hit(ray, enter.t + BOUNDARY_EPSILON..f64::INFINITY, boundary)?;
+
+    let t_enter = enter.t.max(ray_range.start);
+    let t_exit = exit.t.min(ray_range.end);
+    if t_enter >= t_exit {
+        return None;
+    }
+
+    let ray_length = ray.direction.len();
+    let distance_inside = (t_exit - t_enter) * ray_length;
+    let hit_distance = -(1. - random_double(0.0..1.0)).ln() / density;
+    if hit_distance > distance_inside {
+        return None;
+    }
+
+    let t = t_enter + hit_distance / ray_length;
+    Some(HitRecord::new(
+        ray.at(t),
+        t,
+        -ray.direction.unit_vec(),
+        ray,
+        None,
+    ))
+}
+
+/// Where the ray goes in and out of the box from `min` to `max`, as `t`s
+fn hit_box(min: Point3, max: Point3, ray: &Ray) -> Option<(f64, f64)> {
+    let mut t_enter = f64::NEG_INFINITY;
+    let mut t_exit = f64::INFINITY;
+
+    for (origin, direction, min, max) in [
+        (ray.origin.x, ray.direction.x, min.x, max.x),
+        (ray.origin.y, ray.direction.y, min.y, max.y),
+        (ray.origin.z, ray.direction.z, min.z, max.z),
+    ] {
+        // dividing by 0 gives +-infinity, which does the right thing unless
+        // the ray is exactly on the edge of the box
+        let t0 = (min - origin) / direction;
+        let t1 = (max - origin) / direction;
+        t_enter = t_enter.max(t0.min(t1));
+        t_exit = t_exit.min(t0.max(t1));
+    }
+
+    (t_enter < t_exit).then_some((t_enter, t_exit))
+}
+
+/// Same as `hit_constant_medium`, but the density changes from place to place,
+/// following `grid` stretched over the box from `min` to `max`.
+///
+/// We can't just pick a distance anymore, so we use delta tracking (a.k.a.
+/// woodcock tracking): pretend the whole box is as thick as its thickest voxel
+/// (the majorant), pick a distance for that, and then keep the collision with
+/// the chance that there's actually that much stuff there. Otherwise it was a
+/// "null" collision with nothing, and we keep going from there. It comes out
+/// exactly right, just slower the emptier the box is compared to its
+/// thickest voxel.
+pub fn hit_grid_medium(
+    grid: &VoxelGrid,
+    min: Point3,
+    max: Point3,
+    density: f64,
+    ray: &Ray,
+    ray_range: Range<f64>,
+) -> Option<HitRecord> {
+    let (t_enter, t_exit) = hit_box(min, max, ray)?;
+    let t_enter = t_enter.max(ray_range.start);
+    let t_exit = t_exit.min(ray_range.end);
+    if t_enter >= t_exit {
+        return None;
+    }
+
+    let majorant = density * grid.max_value;
+    if majorant <= 0. {
+        return None;
+    }
+
+    let ray_length = ray.direction.len();
+    let mut t = t_enter;
+    loop {
+        t += -(1. - random_double(0.0..1.0)).ln() / (majorant * ray_length);
+        if t >= t_exit {
+            return None;
+        }
+
+        let p = ray.at(t);
+        if density * grid.sample_in_box(p, min, max) > random_double(0.0..1.0) * majorant {
+            return Some(HitRecord::new(p, t, -ray.direction.unit_vec(), ray, None));
+        }
+    }
+}
+
+/// The color of something glowing because it's hot, like fire or lava, from
+/// planck's law at a red, green and blue wavelength. Only the color, it always
+/// has a luminance of 1. `kelvin` goes from about 1000 (dull red) through 6500
+/// (white) to 10000+ (blue-ish).
+pub fn blackbody(kelvin: f64) -> Color {
+    if kelvin <= 0. {
+        return Color::default();
+    }
+
+    // hc / k, in meters times kelvin
+    const C2: f64 = 1.4388e-2;
+    let planck = |nanometers: f64| {
+        let wavelength = nanometers * 1e-9;
+        1. / (wavelength.powi(5) * ((C2 / (wavelength * kelvin)).exp() - 1.))
+    };
+
+    let color = vec3![planck(610.), planck(550.), planck(465.)];
+    let l = luminance(color);
+    if l > 0. && l.is_finite() {
+        color / l
+    } else {
+        Color::default()
+    }
+}
+
+/// Emission for fire and explosions, to go in `MaterialType::Volume`'s
+/// `emission`. Looks up the temperature (in kelvin) in a grid stretched over
+/// the box from `min` to `max`, the same way `ObjectType::GridMedium` does
+/// for density.
+///
+/// Hotter things get a lot brighter (stefan-boltzmann says with the 4th power
+/// of the temperature), so the brightness is `strength * (kelvin / 1000)^4`.
+#[derive(Debug, Clone)]
+pub struct Blackbody {
+    pub temperature: Rc<VoxelGrid>,
+    pub min: Point3,
+    pub max: Point3,
+    pub strength: f64,
+}
+
+impl Texture for Blackbody {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let kelvin = self.temperature.sample_in_box(*p, self.min, self.max);
+        self.strength * (kelvin / 1000.).powi(4) * blackbody(kelvin)
+    }
+}
+
+/// How thick the `Atmosphere` is at each height
+#[derive(Debug, Clone, Copy)]
+pub enum AtmosphereDensity {
+    /// The same everywhere. There's no getting out of it, so with the `Sky`
+    /// background the sky just fades away into the fog.
+    Homogeneous { density: f64 },
+    /// Ground fog, `density` at `base_height` and below, and thinning out
+    /// above it by a factor of e every `1 / falloff` units
+    Exponential {
+        density: f64,
+        base_height: f64,
+        falloff: f64,
+    },
+}
+
+impl AtmosphereDensity {
+    fn at(&self, y: f64) -> f64 {
+        match *self {
+            AtmosphereDensity::Homogeneous { density } => density,
+            AtmosphereDensity::Exponential {
+                density,
+                base_height,
+                falloff,
+            } => density * (-falloff * (y - base_height).max(0.)).exp(),
+        }
+    }
+}
+
+/// Fog that fills up the whole scene, so every ray has a chance of bumping
+/// into it, even the ones that don't hit anything. With a light in the scene
+/// that's what makes light shafts ("god rays") show up.
+#[derive(Debug, Clone)]
+pub struct Atmosphere {
+    pub density: AtmosphereDensity,
+    /// How much light survives bouncing off of the fog
+    pub albedo: Color,
+    pub phase: PhaseFunction,
+}
+
+impl Atmosphere {
+    /// Picks where along `ray_range` the ray bumps into the fog (if it does),
+    /// and which way it goes from there. `ray_range` should end at whatever
+    /// the ray hit, or at infinity if it didn't hit anything.
+    ///
+    /// Same delta tracking as `hit_grid_medium`, with the thickest the fog
+    /// gets along the ray as the majorant.
+    pub fn scatter(&self, ray: &Ray, ray_range: Range<f64>) -> Option<Scatter> {
+        let direction = ray.direction;
+        // the lowest the ray goes, which is where the fog is thickest
+        let lowest = if direction.y < 0. {
+            ray.at(ray_range.end).y
+        } else {
+            ray.at(ray_range.start).y
+        };
+        let majorant = self.density.at(lowest);
+        if majorant <= 0. || !majorant.is_finite() {
+            return None;
+        }
+
+        let ray_length = direction.len();
+        let mut t = ray_range.start;
+        loop {
+            t += -(1. - random_double(0.0..1.0)).ln() / (majorant * ray_length);
+            if t >= ray_range.end {
+                return None;
+            }
+
+            let p = ray.at(t);
+            let density = self.density.at(p.y);
+            if density > random_double(0.0..1.0) * majorant {
+                return Some(Scatter {
+                    scattered: Ray {
+                        origin: p,
+                        direction: self.phase.sample(&direction),
+                        ray_type: RayType::Diffuse,
+                    },
+                    attenuation: self.albedo,
+                });
+            }
+            // going up into air that's so thin that we'd basically never hit
+            // anything anymore, otherwise rays into the sky would go forever
+            if direction.y >= 0. && density < 1e-6 * majorant {
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::vec::*;
+use crate::vec3;
+
+/// Every grid file starts with this, so we don't try to read some random file
+/// as a grid
+const GRID_MAGIC: [u8; 4] = *b"VOXG";
+
+/// A dense 3D grid of numbers (density, temperature, ...), like a stack of
+/// grayscale images. Stored with x changing the fastest, then y, then z.
+pub struct VoxelGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub values: Vec<f32>,
+    /// The biggest value in the grid, which is what delta tracking needs as
+    /// its majorant
+    pub max_value: f64,
+}
+
+// same as `Image`, nobody wants to see every voxel
+impl fmt::Debug for VoxelGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VoxelGrid")
+            .field("nx", &self.nx)
+            .field("ny", &self.ny)
+            .field("nz", &self.nz)
+            .field("max_value", &self.max_value)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How many voxels are in a `nx` by `ny` by `nz` grid, or an error if that
+/// doesn't even fit in a `usize`
+fn voxel_count(nx: usize, ny: usize, nz: usize) -> Result<usize, Box<dyn Error>> {
+    nx.checked_mul(ny)
+        .and_then(|count| count.checked_mul(nz))
+        .ok_or_else(|| format!("a {nx}x{ny}x{nz} grid is too big").into())
+}
+
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<f32>) -> Result<Self, Box<dyn Error>> {
+        let count = voxel_count(nx, ny, nz)?;
+        if values.len() != count {
+            return Err(format!(
+                "a {nx}x{ny}x{nz} grid needs {count} values, got {}",
+                values.len()
+            )
+            .into());
+        }
+        let max_value = values.iter().fold(0f64, |max, &v| max.max(v as f64));
+
+        Ok(Self {
+            nx,
+            ny,
+            nz,
+            values,
+            max_value,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        VoxelGrid::from_bytes(&fs::read(path)?)
+    }
+
+    /// The file is as simple as it gets, everything little endian:
+    /// - the 4 bytes `VOXG`
+    /// - `nx`, `ny` and `nz` as u32s
+    /// - `nx * ny * nz` f32s, in the same order as `values`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if !bytes.starts_with(&GRID_MAGIC) {
+            return Err("not a voxel grid file".into());
+        }
+
+        let header = bytes
+            .get(4..16)
+            .ok_or("voxel grid ended before the header")?;
+        let dimension = |i: usize| {
+            u32::from_le_bytes([
+                header[i * 4],
+                header[i * 4 + 1],
+                header[i * 4 + 2],
+                header[i * 4 + 3],
+            ]) as usize
+        };
+        let (nx, ny, nz) = (dimension(0), dimension(1), dimension(2));
+
+        let count = voxel_count(nx, ny, nz)?;
+        let data = &bytes[16..];
+        if data.len() / 4 < count {
+            return Err("voxel grid ended before the data".into());
+        }
+        let values = data
+            .chunks_exact(4)
+            .take(count)
+            .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect();
+
+        VoxelGrid::new(nx, ny, nz, values)
+    }
+
+    /// 0 outside of the grid
+    fn voxel(&self, x: isize, y: isize, z: isize) -> f64 {
+        if x < 0 || y < 0 || z < 0 {
+            return 0.;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= self.nx || y >= self.ny || z >= self.nz {
+            return 0.;
+        }
+        self.values[x + self.nx * (y + self.ny * z)] as f64
+    }
+
+    /// Trilinearly filtered value at `p`, where the grid goes from `0, 0, 0` to
+    /// `1, 1, 1`. Voxels are sampled at their centers, and fade out to 0 past
+    /// the edge.
+    pub fn sample(&self, p: Point3) -> f64 {
+        let x = p.x * self.nx as f64 - 0.5;
+        let y = p.y * self.ny as f64 - 0.5;
+        let z = p.z * self.nz as f64 - 0.5;
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (x0, y0, z0) = (x0 as isize, y0 as isize, z0 as isize);
+
+        let mut total = 0.;
+        for (dz, wz) in [(0, 1. - fz), (1, fz)] {
+            for (dy, wy) in [(0, 1. - fy), (1, fy)] {
+                for (dx, wx) in [(0, 1. - fx), (1, fx)] {
+                    total += wx * wy * wz * self.voxel(x0 + dx, y0 + dy, z0 + dz);
+                }
+            }
+        }
+        total
+    }
+
+    /// Same as `sample`, but with the grid stretched over the box from `min`
+    /// to `max` in world space
+    pub fn sample_in_box(&self, p: Point3, min: Point3, max: Point3) -> f64 {
+        let size = max - min;
+        let local = p - min;
+        self.sample(vec3![local.x / size.x, local.y / size.y, local.z / size.z])
+    }
+}